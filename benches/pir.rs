@@ -1,9 +1,12 @@
+use std::sync::Arc;
+
 use criterion::*;
 
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
 
 use raidpir::client::RaidPirClient;
+use raidpir::params::RaidPirParams;
 use raidpir::server::RaidPirServer;
 use raidpir::util::*;
 
@@ -29,16 +32,19 @@ fn bench_query(c: &mut Criterion) {
                         let mut db: Vec<u8> = vec![0; *size];
                         prng.fill_bytes(&mut db);
 
+                        let params = RaidPirParams::new(db.len(), 2, 2, 1).unwrap();
+
+                        let db = Arc::new(db);
                         let mut servers: Vec<RaidPirServer<u8>> = (0..2)
-                            .map(|i| RaidPirServer::new(db.clone(), i, 2, 2, true))
+                            .map(|i| RaidPirServer::with_shared(db.clone(), i, params, true).unwrap())
                             .collect();
 
-                        let client = RaidPirClient::new(db.len(), 2, 2);
+                        let client = RaidPirClient::new(params);
 
-                        let seeds = servers.iter_mut().map(|s| s.seed()).collect();
+                        let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
 
                         bench.iter(|| {
-                            client.query(42, &seeds);
+                            client.query(42, &seeds).unwrap();
                         });
                     });
                 },
@@ -69,20 +75,23 @@ fn bench_response(c: &mut Criterion) {
                         let mut db: Vec<u8> = vec![0; *size];
                         prng.fill_bytes(&mut db);
 
+                        let params = RaidPirParams::new(db.len(), 2, 2, 1).unwrap();
+
+                        let db = Arc::new(db);
                         let mut servers: Vec<RaidPirServer<u8>> = (0..2)
-                            .map(|i| RaidPirServer::new(db.clone(), i, 2, 2, true))
+                            .map(|i| RaidPirServer::with_shared(db.clone(), i, params, true).unwrap())
                             .collect();
 
-                        let client = RaidPirClient::new(db.len(), 2, 2);
+                        let client = RaidPirClient::new(params);
 
                         bench.iter_custom(|iters| {
                             (0..iters)
                                 .map(|_| {
-                                    let seeds = servers.iter_mut().map(|s| s.seed()).collect();
-                                    let queries = client.query(42, &seeds);
+                                    let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
+                                    let queries = client.query(42, &seeds).unwrap();
 
                                     let start = std::time::Instant::now();
-                                    black_box(servers[0].response(seeds[0], &queries[0]));
+                                    black_box(servers[0].response(seeds[0], &queries[0]).unwrap());
                                     start.elapsed()
                                 })
                                 .sum()
@@ -118,14 +127,17 @@ fn bench_preprocess(c: &mut Criterion) {
                         let mut db: Vec<u8> = vec![0; *size];
                         prng.fill_bytes(&mut db);
 
+                        let params = RaidPirParams::new(db.len(), 2, 2, 1).unwrap();
+
+                        let db = Arc::new(db);
                         bench.iter_custom(|iters| {
                             (0..iters)
                                 .map(|_| {
                                     let server: RaidPirServer<u8> =
-                                        RaidPirServer::new(db.clone(), 0, 2, 2, true);
+                                        RaidPirServer::with_shared(db.clone(), 0, params, true).unwrap();
 
                                     let start = std::time::Instant::now();
-                                    black_box(server.preprocess());
+                                    black_box(&server).preprocess();
                                     start.elapsed()
                                 })
                                 .sum()
@@ -169,6 +181,5 @@ fn bench_xoring(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, bench_query, bench_response, bench_xoring);
-//criterion_group!(benches, bench_preprocess);
+criterion_group!(benches, bench_query, bench_response, bench_preprocess, bench_xoring);
 criterion_main!(benches);
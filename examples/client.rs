@@ -1,10 +1,11 @@
 use std::time::{Duration, Instant};
 use std::net::TcpStream;
-use std::io::{Read, Write};
 
 use rayon::iter::*;
 
 use raidpir::client::RaidPirClient;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::{read_message, write_message, Message};
 use raidpir::types::RaidPirData;
 
 fn main() {
@@ -15,13 +16,18 @@ fn main() {
     const REDUNDANCY: usize = 2;
     let index = 234;
 
-    let client = RaidPirClient::new(DB_SIZE, SERVERS, REDUNDANCY);
+    let params = RaidPirParams::new(DB_SIZE, SERVERS, REDUNDANCY, ELEMENT_SIZE).unwrap();
+    let client = RaidPirClient::new(params);
 
     let addresses = vec!["localhost:3333", "localhost:3334"];
 
-    let mut total: f64 = 0.0;
+    const ROUNDS: usize = 100;
 
-    for i in 0..100 {
+    // Connection setup, seed receipt, query computation, sending queries and
+    // receiving responses, combining responses
+    let mut phases = [0.0f64; 5];
+
+    for _ in 0..ROUNDS {
         let t0 = Instant::now();
 
         // Init connections and retrieve seeds
@@ -42,53 +48,58 @@ fn main() {
         let seeds: Vec<u128> = streams
             .par_iter_mut()
             .map(|stream| {
-                let mut seed_bytes = [0; 16];
-                stream.read_exact(&mut seed_bytes).unwrap();
-
-                u128::from_le_bytes(seed_bytes)
+                match read_message(stream).unwrap() {
+                    Message::Hello(p) => params.check_fingerprint(p.fingerprint()).unwrap(),
+                    msg => panic!("Expected Hello, got {:?}", msg),
+                }
+
+                match read_message(stream).unwrap() {
+                    Message::SeedGrant(seed) => seed,
+                    msg => panic!("Expected SeedGrant, got {:?}", msg),
+                }
             })
             .with_max_len(1)
             .collect::<Vec<u128>>();
 
         let t2 = Instant::now();
 
-        let raidpir_queries = client.query(index, &seeds);
+        let raidpir_queries = client.query(index, &seeds).unwrap();
 
         let t3 = Instant::now();
 
         // Send queries and retrieve responses
         let responses: Vec<RaidPirData> = streams
             .par_iter_mut()
-            .zip(raidpir_queries.par_iter())
-            .map(|(stream, raidpir_query)| {
-                //println!("{:?}", raidpir_query.as_slice().len());
-                stream.write(raidpir_query.as_slice()).unwrap();
-
-                let mut response = Vec::with_capacity(ELEMENT_SIZE);
-                stream.read_to_end(&mut response).unwrap();
-
-                RaidPirData::new(response)
+            .zip(seeds.par_iter().zip(raidpir_queries.into_par_iter()))
+            .map(|(stream, (seed, query))| {
+                write_message(stream, &Message::Query { seed: *seed, query }).unwrap();
+
+                match read_message(stream).unwrap() {
+                    Message::Response(response) => RaidPirData::new(response),
+                    msg => panic!("Expected Response, got {:?}", msg),
+                }
             })
             .with_max_len(1)
             .collect();
 
         let t4 = Instant::now();
 
-        let result = client.combine(responses);
+        let result = client.combine(responses).unwrap();
+        assert_eq!(result.as_slice().len(), ELEMENT_SIZE);
 
         let t5 = Instant::now();
 
-        //println!("Connection Setup: {:.4}ms", (t1 - t0).as_secs_f32() * 1000.0);
-        //println!("Seed Recv: {:.4}ms", (t2 - t1).as_secs_f32() * 1000.0);
-        //println!("Query Comp.: {:.4}ms", (t3 - t2).as_secs_f32() * 1000.0);
-        //println!("Query Send/Resp Recv: {:.4}ms", (t4 - t3).as_secs_f32() * 1000.0);
-        //println!("Resp Comb.: {:.4}ms", (t5 - t4).as_secs_f32() * 1000.0);
-        //println!("Total Online Time: {:.4}ms", (t5-t0).as_secs_f64() * 1000.0);
-
-        //assert!(result.as_slice() == b"deadbeef");
-
-        total += (t5 - t0).as_secs_f64() * 1000.0;
+        let times = [t0, t1, t2, t3, t4, t5];
+        for (phase, window) in phases.iter_mut().zip(times.windows(2)) {
+            *phase += (window[1] - window[0]).as_secs_f64() * 1000.0;
+        }
     }
 
-    println!("Total Online Time: {:.4}ms", total / 100.0);
+    let average = |ms: f64| ms / ROUNDS as f64;
+    println!("Connection Setup: {:.4}ms", average(phases[0]));
+    println!("Seed Recv: {:.4}ms", average(phases[1]));
+    println!("Query Comp.: {:.4}ms", average(phases[2]));
+    println!("Query Send/Resp Recv: {:.4}ms", average(phases[3]));
+    println!("Resp Comb.: {:.4}ms", average(phases[4]));
+    println!("Total Online Time: {:.4}ms", average(phases.iter().sum()));
 }
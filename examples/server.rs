@@ -1,12 +1,11 @@
 use std::time::Instant;
 use std::net::TcpListener;
-use std::io::{Read, Write};
-
-use bitvec::vec::BitVec;
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
 
 use raidpir::server::RaidPirServer;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::{read_message, write_message, Message};
 use raidpir::types::RaidPirData;
 
 fn main() {
@@ -27,11 +26,12 @@ fn main() {
         prng.fill_bytes(&mut buffer);
         db.push(buffer);
     }
-    db[index] = b"deadbeef".to_vec();
+    db[index][..8].copy_from_slice(b"deadbeef");
 
     let raidpir_db: Vec<RaidPirData> = db.iter().map(|x| RaidPirData::new(x.clone())).collect();
 
-    let server = RaidPirServer::new(raidpir_db, id, SERVERS, REDUNDANCY, true);
+    let params = RaidPirParams::new(DB_SIZE, SERVERS, REDUNDANCY, ELEMENT_SIZE).unwrap();
+    let server = RaidPirServer::new(raidpir_db, id, params, true).unwrap();
     server.preprocess();
 
     let addr = format!("localhost:{}", port);
@@ -39,21 +39,27 @@ fn main() {
 
     println!("Listening on {:?}...", listener.local_addr().unwrap());
 
-    let mut query: Vec<u8> = vec![0; DB_SIZE / SERVERS / 8];
     for stream in listener.incoming() {
         match stream {
             Ok(mut stream) => {
-                let seed = server.seed();
-                stream.write(&seed.clone().to_le_bytes()).unwrap();
+                write_message(&mut stream, &Message::Hello(params)).unwrap();
+
+                let seed = server.seed().unwrap();
+                write_message(&mut stream, &Message::SeedGrant(seed)).unwrap();
 
-                stream.read_exact(&mut query).unwrap();
+                let query = match read_message(&mut stream) {
+                    Ok(Message::Query { seed: s, query }) if s == seed => query,
+                    other => {
+                        println!("Unexpected message: {:?}", other);
+                        continue;
+                    }
+                };
 
-                let bitvec = BitVec::from_vec(query.clone());
                 let t0 = Instant::now();
-                let response = server.response(seed, &bitvec);
+                let response = server.response(seed, &query).unwrap();
                 println!("Response Comp.: {:.4}ms", t0.elapsed().as_secs_f64() * 1000.0);
 
-                stream.write(response.as_slice()).unwrap();
+                write_message(&mut stream, &Message::Response(response.into())).unwrap();
             },
             Err(e) => {
                 println!("{:?}", e);
@@ -0,0 +1,305 @@
+//! Async client and server on top of tokio. Requires the `async` feature.
+//!
+//! Speaks the same [crate::protocol] as the blocking implementations, so
+//! async clients can talk to blocking servers and vice versa. All
+//! CPU-intensive work (preprocessing, responses, query calculation) is moved
+//! off the reactor using [tokio::task::spawn_blocking].
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitvec::prelude::*;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+
+use crate::client::RaidPirClient;
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::protocol::{check_body, decode_body, encode, frame_buffer, frame_length, Message};
+use crate::server::RaidPirServer;
+use crate::service::{prune_granted, MAX_OUTSTANDING_SEEDS};
+use crate::types::{RaidPirBytes, RaidPirData, RaidPirElement};
+
+/**
+ * Read a single framed message from the given reader.
+ */
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Message> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).await?;
+
+    let len = frame_length(len)?;
+    let mut body = frame_buffer(len);
+    reader.take(len as u64).read_to_end(&mut body).await?;
+    check_body(&body, len)?;
+
+    decode_body(&body)
+}
+
+/**
+ * Write a single framed message to the given writer.
+ */
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, msg: &Message) -> Result<()> {
+    writer.write_all(&encode(msg)).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Run a CPU-intensive closure on tokio's blocking thread pool.
+async fn blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| RaidPirError::Io(std::io::Error::other(e)))
+}
+
+/**
+ * Serve lookups on the given connection until the client closes it.
+ *
+ * Async equivalent of [crate::service::serve_connection]. The connection is
+ * closed with a [ErrorKind::TimedOut] error if the client doesn't send a
+ * complete message within `idle_timeout`.
+ */
+pub async fn serve_connection<T, S>(
+    server: Arc<RaidPirServer<T>>,
+    stream: &mut S,
+    idle_timeout: Duration,
+) -> Result<()>
+where
+    T: RaidPirBytes + Send + Sync + 'static,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_message(stream, &Message::Hello(*server.params())).await?;
+
+    let mut granted = HashSet::new();
+    grant_seeds(&server, stream, &mut granted, 1).await?;
+
+    loop {
+        let msg = match tokio::time::timeout(idle_timeout, read_message(stream)).await {
+            Ok(Ok(msg)) => msg,
+            // Client closed the connection between two messages.
+            Ok(Err(RaidPirError::Io(e))) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                return Err(RaidPirError::Io(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("client idle for {:?}", idle_timeout),
+                )))
+            }
+        };
+
+        let result = match msg {
+            Message::Query { seed, query } if granted.remove(&seed) || server.verify_seed(seed) => {
+                let server = server.clone();
+                match blocking(move || server.response(seed, &query)).await? {
+                    Ok(answer) => write_message(stream, &Message::Response(answer.to_bytes())).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Message::Query { seed, .. } => Err(RaidPirError::UnknownSeed(seed)),
+            Message::SeedRequest(count) => grant_seeds(&server, stream, &mut granted, count as usize).await,
+            Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+            msg => Err(RaidPirError::Protocol(format!("expected Query or SeedRequest, got {}", msg.name()))),
+        };
+
+        if let Err(e) = result {
+            let _ = write_message(stream, &Message::Error(e.to_string())).await;
+            return Err(e);
+        }
+    }
+}
+
+async fn grant_seeds<T, S>(
+    server: &Arc<RaidPirServer<T>>,
+    stream: &mut S,
+    granted: &mut HashSet<u128>,
+    count: usize,
+) -> Result<()>
+where
+    T: RaidPirElement + Send + Sync + 'static,
+    S: AsyncWrite + Unpin,
+{
+    if granted.len() + count > MAX_OUTSTANDING_SEEDS {
+        // Checking the seed store might touch the disk.
+        let server = server.clone();
+        let mut taken = std::mem::take(granted);
+        *granted = blocking(move || prune_granted(&server, &mut taken).map(|_| taken)).await??;
+    }
+
+    if granted.len() + count > MAX_OUTSTANDING_SEEDS {
+        return Err(RaidPirError::Protocol(format!(
+            "more than {} outstanding seeds requested",
+            MAX_OUTSTANDING_SEEDS
+        )));
+    }
+
+    for _ in 0..count {
+        // Might have to refill the queue, so don't block the reactor.
+        let seed = {
+            let server = server.clone();
+            blocking(move || server.seed()).await??
+        };
+        granted.insert(seed);
+        write_message(stream, &Message::SeedGrant(seed)).await?;
+    }
+
+    Ok(())
+}
+
+/**
+ * Accept and serve connections until `shutdown` completes.
+ *
+ * Each connection is served in its own task, and closed once the client has
+ * been idle for `idle_timeout`. After shutdown, no new connections are
+ * accepted, but open connections are served to completion.
+ */
+pub async fn serve<T, F>(
+    listener: TcpListener,
+    server: Arc<RaidPirServer<T>>,
+    idle_timeout: Duration,
+    shutdown: F,
+) -> Result<()>
+where
+    T: RaidPirBytes + Send + Sync + 'static,
+    F: Future<Output = ()>,
+{
+    tokio::pin!(shutdown);
+
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => {
+                let (mut stream, peer) = match accepted {
+                    Ok(x) => x,
+                    Err(e) => {
+                        log::warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+
+                let server = server.clone();
+                connections.spawn(async move {
+                    stream.set_nodelay(true)?;
+                    serve_connection(server, &mut stream, idle_timeout)
+                        .await
+                        .map_err(|e| {
+                            log::warn!("{}: {}", peer, e);
+                            e
+                        })
+                });
+            }
+            // Reap finished connections, so the set doesn't grow forever.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+/// Open a connection and receive parameters and seed.
+async fn fetch_seed<A: ToSocketAddrs>(address: A) -> Result<(TcpStream, RaidPirParams, u128)> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+
+    let params = match read_message(&mut stream).await? {
+        Message::Hello(params) => params,
+        Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+        msg => return Err(RaidPirError::Protocol(format!("expected Hello, got {}", msg.name()))),
+    };
+
+    match read_message(&mut stream).await? {
+        Message::SeedGrant(seed) => Ok((stream, params, seed)),
+        Message::Error(msg) => Err(RaidPirError::Remote(msg)),
+        msg => Err(RaidPirError::Protocol(format!("expected SeedGrant, got {}", msg.name()))),
+    }
+}
+
+/// Send query and receive response on an open connection.
+async fn fetch_response(stream: &mut TcpStream, seed: u128, query: BitVec<Lsb0, u8>) -> Result<RaidPirData> {
+    write_message(stream, &Message::Query { seed, query }).await?;
+
+    match read_message(stream).await? {
+        Message::Response(data) => Ok(RaidPirData::new(data)),
+        Message::Error(msg) => Err(RaidPirError::Remote(msg)),
+        msg => Err(RaidPirError::Protocol(format!("expected Response, got {}", msg.name()))),
+    }
+}
+
+/// Wrap a future with a timeout, turning expiry into an I/O error.
+async fn with_timeout<F, R>(timeout: Duration, f: F) -> Result<R>
+where
+    F: Future<Output = Result<R>>,
+{
+    tokio::time::timeout(timeout, f).await.unwrap_or_else(|_| {
+        Err(RaidPirError::Io(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("timed out after {:?}", timeout),
+        )))
+    })
+}
+
+/**
+ * Privately retrieve the element at `index` from the given servers.
+ *
+ * Servers need to be given in order of their id. All servers are queried
+ * concurrently, and each phase (seed retrieval, query/response) has to
+ * complete within `timeout` for every server.
+ *
+ * If any server fails, all outstanding requests to the other servers are
+ * cancelled. Dropping the returned future cancels all requests as well.
+ */
+pub async fn lookup<A>(servers: &[A], index: usize, timeout: Duration) -> Result<RaidPirData>
+where
+    A: ToSocketAddrs + Clone + Send + 'static,
+{
+    if servers.is_empty() {
+        return Err(RaidPirError::NoServers);
+    }
+
+    // Tasks in a JoinSet are aborted when it is dropped, e.g. when returning
+    // early because one server failed.
+    let mut tasks = JoinSet::new();
+    for (i, address) in servers.iter().cloned().enumerate() {
+        tasks.spawn(async move { (i, with_timeout(timeout, fetch_seed(address)).await) });
+    }
+
+    let mut connections = Vec::with_capacity(servers.len());
+    while let Some(joined) = tasks.join_next().await {
+        let (i, result) = joined.map_err(|e| RaidPirError::Io(std::io::Error::other(e)))?;
+        let (stream, params, seed) = result.map_err(|e| e.at_server(i))?;
+        connections.push((i, stream, params, seed));
+    }
+    connections.sort_by_key(|(i, _, _, _)| *i);
+
+    let params = connections[0].2;
+    for (i, _, p, _) in connections.iter() {
+        params.check_fingerprint(p.fingerprint()).map_err(|e| e.at_server(*i))?;
+    }
+
+    let seeds: Vec<u128> = connections.iter().map(|(_, _, _, seed)| *seed).collect();
+    let queries = blocking(move || RaidPirClient::new(params).query(index, &seeds)).await??;
+
+    let mut tasks = JoinSet::new();
+    for ((i, mut stream, _, seed), query) in connections.into_iter().zip(queries) {
+        tasks.spawn(async move { (i, with_timeout(timeout, fetch_response(&mut stream, seed, query)).await) });
+    }
+
+    let mut responses = Vec::with_capacity(servers.len());
+    while let Some(joined) = tasks.join_next().await {
+        let (i, result) = joined.map_err(|e| RaidPirError::Io(std::io::Error::other(e)))?;
+        responses.push((i, result.map_err(|e| e.at_server(i))?));
+    }
+    responses.sort_by_key(|(i, _)| *i);
+
+    RaidPirClient::new(params).combine(responses.into_iter().map(|(_, r)| r).collect())
+}
@@ -0,0 +1,242 @@
+//! RAID-PIR command-line client.
+//!
+//! Privately retrieves one or more records from a set of `raidpir-server`s
+//! and writes them to stdout or a file.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use raidpir::client::RaidPirClient;
+use raidpir::pool::SeedPool;
+use raidpir::service::{Connection, MAX_OUTSTANDING_SEEDS};
+use raidpir::transport::TcpTransport;
+use raidpir::types::RaidPirData;
+
+const USAGE: &str = "\
+Usage: raidpir-client [OPTIONS] --server <ADDR>... --index <N>...
+
+Options:
+    --server <ADDR>     Server address, in order of server id (repeatable)
+    --index <N>         Index of record to retrieve (repeatable, or comma-separated)
+    --output <PATH>     Write records to file instead of stdout
+    --hex               Write records as hex, one line per index
+    --timeout <SECS>    Read/write timeout for connections [default: 60]
+    --timings           Print per-phase timings to stderr
+    --prefetch          Fetch and expand seeds for all lookups before the first one
+    --help              Print this message";
+
+#[derive(Debug, Default)]
+struct Config {
+    servers: Vec<String>,
+    indices: Vec<usize>,
+    output: Option<PathBuf>,
+    hex: bool,
+    timeout: Option<u64>,
+    timings: bool,
+    prefetch: bool,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Self::default();
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("Missing value for {}", name));
+
+            match arg.as_str() {
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "--server" => config.servers.push(value("--server")?),
+                "--index" => {
+                    for index in value("--index")?.split(',') {
+                        let index = index.trim().parse().map_err(|_| format!("Invalid index: {:?}", index))?;
+                        config.indices.push(index);
+                    }
+                }
+                "--output" => config.output = Some(PathBuf::from(value("--output")?)),
+                "--hex" => config.hex = true,
+                "--timeout" => {
+                    let timeout = value("--timeout")?;
+                    config.timeout = Some(timeout.parse().map_err(|_| format!("Invalid timeout: {:?}", timeout))?);
+                }
+                "--timings" => config.timings = true,
+                "--prefetch" => config.prefetch = true,
+                _ => return Err(format!("Unexpected argument: {}", arg)),
+            }
+        }
+
+        if config.servers.len() < 2 || config.indices.is_empty() {
+            return Err("At least two --server and one --index are required".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+/// Durations of the individual phases of a lookup.
+#[derive(Debug, Default)]
+struct Timings {
+    connect: Duration,
+    seeds: Duration,
+    query: Duration,
+    responses: Duration,
+    combine: Duration,
+}
+
+impl Timings {
+    fn print(&self, index: usize) {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let total = self.connect + self.seeds + self.query + self.responses + self.combine;
+
+        eprintln!("Index {}:", index);
+        eprintln!("  Connection Setup:      {:.4}ms", ms(self.connect));
+        eprintln!("  Seed Recv:             {:.4}ms", ms(self.seeds));
+        eprintln!("  Query Comp.:           {:.4}ms", ms(self.query));
+        eprintln!("  Query Send/Resp Recv:  {:.4}ms", ms(self.responses));
+        eprintln!("  Resp Comb.:            {:.4}ms", ms(self.combine));
+        eprintln!("  Total Online Time:     {:.4}ms", ms(total));
+    }
+}
+
+fn connect(servers: &[String], timeout: Duration) -> Result<Vec<Connection<TcpTransport>>, Box<dyn Error>> {
+    let connections = servers
+        .par_iter() // Establish connections in parallel
+        .map(|address| {
+            TcpTransport::connect(address, timeout)
+                .and_then(Connection::open)
+                .map_err(|e| format!("{}: {}", address, e))
+        })
+        .with_max_len(1) // Ensure each iteration gets a thread
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let params = *connections[0].params();
+    for (address, connection) in servers.iter().zip(connections.iter()) {
+        params
+            .check_fingerprint(connection.params().fingerprint())
+            .map_err(|e| format!("{}: {}", address, e))?;
+    }
+
+    Ok(connections)
+}
+
+fn lookup(
+    connections: &mut [Connection<TcpTransport>],
+    pool: &mut SeedPool,
+    index: usize,
+) -> Result<(RaidPirData, Timings), Box<dyn Error>> {
+    let mut timings = Timings::default();
+    let client = RaidPirClient::new(*connections[0].params());
+
+    let t1 = Instant::now();
+
+    // Prefetched seeds are already expanded, otherwise fetch and expand
+    // them now.
+    let prepared = match pool.take(1).pop() {
+        Some(prepared) => prepared,
+        None => {
+            let seeds: Vec<u128> = connections
+                .par_iter_mut()
+                .map(|connection| connection.take_seeds(1).map(|seeds| seeds[0]))
+                .with_max_len(1)
+                .collect::<Result<_, _>>()?;
+
+            timings.seeds = t1.elapsed();
+
+            client.prepare(&seeds)?
+        }
+    };
+
+    let t2 = t1 + timings.seeds;
+
+    let seeds = prepared.seeds().to_vec();
+    let queries = client.query_prepared(index, prepared)?;
+
+    let t3 = Instant::now();
+    timings.query = t3 - t2;
+
+    let responses: Vec<RaidPirData> = connections
+        .par_iter_mut()
+        .zip(seeds.into_par_iter().zip(queries.into_par_iter()))
+        .map(|(connection, query)| connection.pipeline(vec![query], 1).map(|mut r| r.remove(0)))
+        .with_max_len(1)
+        .collect::<Result<_, _>>()?;
+
+    let t4 = Instant::now();
+    timings.responses = t4 - t3;
+
+    let result = client.combine(responses)?;
+
+    timings.combine = t4.elapsed();
+
+    Ok((result, timings))
+}
+
+fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let timeout = Duration::from_secs(config.timeout.unwrap_or(60));
+
+    let mut output: Box<dyn Write> = match config.output.as_ref() {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+
+    // All lookups share the same connections, so only the first one pays
+    // for connection setup.
+    let t0 = Instant::now();
+    let mut connections = connect(&config.servers, timeout)?;
+    let mut connect_time = Some(t0.elapsed());
+
+    let mut pool = SeedPool::default();
+    if config.prefetch {
+        let t0 = Instant::now();
+        // Servers limit how many seeds a connection may hold, the remaining
+        // lookups fetch theirs on demand.
+        pool.fill(&mut connections, config.indices.len().min(MAX_OUTSTANDING_SEEDS))?;
+
+        if config.timings {
+            eprintln!("Prefetched {} seeds in {:.4}ms", pool.len(), t0.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    for index in config.indices.iter() {
+        let (record, mut timings) = lookup(&mut connections, &mut pool, *index)?;
+        timings.connect = connect_time.take().unwrap_or_default();
+
+        if config.timings {
+            timings.print(*index);
+        }
+
+        if config.hex {
+            let hex: String = record.as_slice().iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(output, "{}: {}", index, hex)?;
+        } else {
+            output.write_all(record.as_slice())?;
+        }
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+fn main() {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(config) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
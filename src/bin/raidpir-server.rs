@@ -0,0 +1,468 @@
+//! RAID-PIR server daemon.
+//!
+//! Serves a database file to many concurrent clients using the
+//! [raidpir::protocol]. Configuration is read from command line flags and/or
+//! a config file with one `key = value` pair per line, using the same keys as
+//! the long flags (e.g. `redundancy = 2`). Flags take precedence.
+
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use raidpir::database::read_contiguous_database;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::Message;
+use raidpir::russians::{memory_estimate, DEFAULT_WINDOW};
+use raidpir::server::{RaidPirServer, DEFAULT_LOW_WATER_MARK, DEFAULT_QUEUE_CAPACITY};
+use raidpir::service::serve_connection;
+use raidpir::storage::{MappedDatabase, RaidPirDatabase, Storage};
+use raidpir::store::FileStore;
+use raidpir::transport::{TcpTransport, Transport};
+use raidpir::types::RaidPirData;
+
+/// Time after which seeds handed out but never queried are evicted, unless
+/// configured otherwise.
+const DEFAULT_SEED_TTL: Duration = Duration::from_secs(600);
+
+const USAGE: &str = "\
+Usage: raidpir-server [OPTIONS]
+
+Options:
+    --config <PATH>            Read options from config file
+    --database <PATH>          Database file to serve
+    --raw-element-size <N>     Treat database as headerless records of N bytes
+    --mmap                     Serve the database from a memory mapping instead of
+                               reading it into memory
+    --id <N>                   Id of this server (0-based)
+    --servers <N>              Total number of servers
+    --redundancy <N>           Number of servers storing each chunk [default: 2]
+    --listen <ADDR>            Address to listen on [default: 0.0.0.0:3333]
+    --max-connections <N>      Number of connections served at once, each on its own
+                               thread; more are turned away [default: 1024]
+    --preprocess-threads <N>   Number of threads for preprocessing [default: #CPUs]
+    --queue-capacity <N>       Number of preprocessed seeds to keep [default: 32]
+    --low-water-mark <N>       Refill queue in the background below N seeds [default: 8]
+    --no-worker                Only refill the queue when it runs empty
+    --seed-ttl <SECS>          Evict seeds not queried within SECS, 0 to keep forever [default: 600]
+    --max-handed-out <N>       Evict oldest seeds beyond N not yet queried [default: unlimited]
+    --seed-key <HEX>           Issue stateless seeds under this 128-bit key, shared by
+                               all instances of this server; prefer the config file.
+                               Requires a nonzero seed TTL, and a shared seed store
+                               to answer each seed only once across instances
+    --seed-store <DIR>         Keep handed out seeds in DIR, shared by all instances
+                               of this server [default: in memory]
+    --snapshot <PATH>          Restore preprocessed seeds from PATH on startup, and
+                               save them there on shutdown
+    --timeout <SECS>           Read/write timeout for connections [default: 60]
+    --no-russians              Disable Four-Russians lookup tables
+    --russians-window <BITS>   Width of Four-Russians windows, 1 to 16 [default: 8]
+    --russians-file <PATH>     Memory-map Four-Russians tables from PATH, building and
+                               saving them there first if needed
+    --preprocess-russians      Also use Four-Russians lookup tables for preprocessing,
+                               at `redundancy` times the memory
+    --help                     Print this message";
+
+#[derive(Debug)]
+struct Config {
+    database: Option<PathBuf>,
+    raw_element_size: Option<usize>,
+    mmap: bool,
+    id: Option<usize>,
+    servers: Option<usize>,
+    redundancy: usize,
+    listen: String,
+    max_connections: usize,
+    preprocess_threads: usize,
+    queue_capacity: usize,
+    low_water_mark: usize,
+    worker: bool,
+    seed_ttl: u64,
+    max_handed_out: Option<usize>,
+    seed_key: Option<[u8; 16]>,
+    seed_store: Option<PathBuf>,
+    snapshot: Option<PathBuf>,
+    timeout: u64,
+    russians: bool,
+    russians_window: usize,
+    russians_file: Option<PathBuf>,
+    preprocess_russians: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database: None,
+            raw_element_size: None,
+            mmap: false,
+            id: None,
+            servers: None,
+            redundancy: 2,
+            listen: "0.0.0.0:3333".to_string(),
+            max_connections: 1024,
+            preprocess_threads: 0,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            low_water_mark: DEFAULT_LOW_WATER_MARK,
+            worker: true,
+            seed_ttl: DEFAULT_SEED_TTL.as_secs(),
+            max_handed_out: None,
+            seed_key: None,
+            seed_store: None,
+            snapshot: None,
+            timeout: 60,
+            russians: true,
+            russians_window: DEFAULT_WINDOW,
+            russians_file: None,
+            preprocess_russians: false,
+        }
+    }
+}
+
+impl Config {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value.parse().map_err(|_| format!("Invalid value for {}: {:?}", key, value))
+        }
+
+        fn parse_key(key: &str, value: &str) -> Result<[u8; 16], String> {
+            let invalid = || format!("Invalid value for {}: expected 32 hex digits", key);
+            if value.len() != 32 || !value.is_ascii() {
+                return Err(invalid());
+            }
+
+            let mut bytes = [0; 16];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&value[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+            }
+
+            Ok(bytes)
+        }
+
+        match key {
+            "database" => self.database = Some(PathBuf::from(value)),
+            "raw-element-size" => self.raw_element_size = Some(parse(key, value)?),
+            "mmap" => self.mmap = parse(key, value)?,
+            "id" => self.id = Some(parse(key, value)?),
+            "servers" => self.servers = Some(parse(key, value)?),
+            "redundancy" => self.redundancy = parse(key, value)?,
+            "listen" => self.listen = value.to_string(),
+            "max-connections" => self.max_connections = parse(key, value)?,
+            "preprocess-threads" => self.preprocess_threads = parse(key, value)?,
+            "queue-capacity" => self.queue_capacity = parse(key, value)?,
+            "low-water-mark" => self.low_water_mark = parse(key, value)?,
+            "worker" => self.worker = parse(key, value)?,
+            "seed-ttl" => self.seed_ttl = parse(key, value)?,
+            "max-handed-out" => self.max_handed_out = Some(parse(key, value)?),
+            "seed-key" => self.seed_key = Some(parse_key(key, value)?),
+            "seed-store" => self.seed_store = Some(PathBuf::from(value)),
+            "snapshot" => self.snapshot = Some(PathBuf::from(value)),
+            "timeout" => self.timeout = parse(key, value)?,
+            "russians" => self.russians = parse(key, value)?,
+            "russians-window" => self.russians_window = parse(key, value)?,
+            "russians-file" => self.russians_file = Some(PathBuf::from(value)),
+            "preprocess-russians" => self.preprocess_russians = parse(key, value)?,
+            _ => return Err(format!("Unknown option: {}", key)),
+        }
+
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}:{}: expected key = value", path, i + 1))?;
+            self.set(key.trim(), value.trim().trim_matches('"'))?;
+        }
+
+        Ok(())
+    }
+
+    fn from_args() -> Result<Self, String> {
+        let mut config = Self::default();
+        let mut flags: Vec<(String, String)> = Vec::new();
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let key = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("Unexpected argument: {}", arg))?;
+
+            match key {
+                "help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "no-russians" => flags.push(("russians".to_string(), "false".to_string())),
+                "mmap" => flags.push((key.to_string(), "true".to_string())),
+                "preprocess-russians" => flags.push((key.to_string(), "true".to_string())),
+                "no-worker" => flags.push(("worker".to_string(), "false".to_string())),
+                _ => {
+                    let value = args.next().ok_or_else(|| format!("Missing value for --{}", key))?;
+                    flags.push((key.to_string(), value));
+                }
+            }
+        }
+
+        // Load config file first, so flags can override its values.
+        if let Some((_, path)) = flags.iter().find(|(k, _)| k == "config") {
+            config.load_file(path)?;
+        }
+
+        for (key, value) in flags.iter().filter(|(k, _)| k != "config") {
+            config.set(key, value)?;
+        }
+
+        if config.database.is_none() || config.id.is_none() || config.servers.is_none() {
+            return Err("--database, --id and --servers are required".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("[{}] {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+fn handle_connection(server: &RaidPirServer<RaidPirData>, stream: TcpStream, timeout: Duration) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|_| "unknown peer".to_string());
+
+    let result = stream
+        .set_nodelay(true)
+        .and_then(|_| stream.set_read_timeout(Some(timeout)))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.into())
+        .and_then(|_| serve_connection(server, &mut TcpTransport::new(stream)));
+
+    match result {
+        Ok(()) => log::debug!("{}: served lookup", peer),
+        Err(e) => log::warn!("{}: {}", peer, e),
+    }
+}
+
+/// Tell a client that all connection slots are taken.
+fn reject_connection(stream: TcpStream, timeout: Duration) {
+    log::warn!("Too many open connections, rejecting one");
+
+    let _ = stream.set_write_timeout(Some(timeout));
+    let _ = TcpTransport::new(stream).send(&Message::Error("too many open connections".to_string()));
+}
+
+/// Create a server for the given database, without Four-Russians tables.
+fn create_server<S>(db: S, config: &Config) -> Result<RaidPirServer<RaidPirData>, Box<dyn Error>>
+where
+    S: Storage<RaidPirData> + 'static,
+{
+    let params = RaidPirParams::new(db.len(), config.servers.unwrap(), config.redundancy, db.element_size())?;
+
+    log::info!(
+        "{} {} records of {} bytes from {}",
+        if config.mmap { "Mapped" } else { "Loaded" },
+        params.blocks(), params.element_size(), config.database.as_ref().unwrap().display()
+    );
+
+    // A mapped database is only paged in where it is accessed, one read into
+    // memory is cut down to the records this server needs.
+    let id = config.id.unwrap();
+    if config.mmap || params.window_len() >= params.blocks_padded() {
+        return Ok(RaidPirServer::with_storage(db, id, params, false)?);
+    }
+
+    let window = RaidPirDatabase::from_window::<RaidPirData, _>(&db, id, &params)?;
+    drop(db);
+    log::info!("Keeping {} records in the window of server {}", window.len(), id);
+
+    Ok(RaidPirServer::with_window(window, id, params, false)?)
+}
+
+fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let path = config.database.as_ref().unwrap();
+    let mut server = match (config.mmap, config.raw_element_size) {
+        (true, Some(size)) => create_server(MappedDatabase::open_raw(path, size)?, &config)?,
+        (true, None) => create_server(MappedDatabase::open(path)?, &config)?,
+        (false, Some(size)) => create_server(RaidPirDatabase::from_bytes(size, &std::fs::read(path)?)?, &config)?,
+        (false, None) => create_server(read_contiguous_database(path)?, &config)?,
+    };
+    let params = *server.params();
+
+    server.set_russians_window(config.russians_window)?;
+
+    let russians = config.russians;
+    let russians_file = config.russians_file.as_ref().filter(|_| russians);
+    let mapped = match russians_file.filter(|path| path.exists()) {
+        Some(path) => match server.load_russians(path) {
+            Ok(()) => {
+                log::info!("Mapped Four-Russians tables from {}", path.display());
+                true
+            }
+            Err(e) => {
+                log::warn!("Ignoring Four-Russians tables {}: {}", path.display(), e);
+                false
+            }
+        },
+        None => false,
+    };
+
+    if config.russians && !mapped {
+        log::info!(
+            "Building Four-Russians tables (about {} MiB)",
+            memory_estimate(&params, config.russians_window, config.preprocess_russians)? >> 20
+        );
+    }
+
+    // Builds only the tables that were not mapped.
+    server.set_russians(config.russians)?;
+    server.set_preprocess_russians(config.russians && config.preprocess_russians)?;
+
+    if let Some(path) = russians_file.filter(|_| !mapped) {
+        server.save_russians(path)?;
+        log::info!("Saved Four-Russians tables to {}", path.display());
+    }
+    server.set_parallelism(config.preprocess_threads)?;
+    server.set_queue_size(config.queue_capacity, config.low_water_mark)?;
+    server.set_seed_expiry(
+        Some(Duration::from_secs(config.seed_ttl)).filter(|ttl| !ttl.is_zero()),
+        config.max_handed_out,
+    )?;
+    if let Some(key) = config.seed_key {
+        server.set_seed_key(key)?;
+    }
+    if let Some(dir) = config.seed_store.as_ref() {
+        server.set_seed_store(FileStore::open(dir, params.element_size())?);
+        log::info!("Sharing handed out seeds in {}", dir.display());
+    }
+    if let Some(path) = config.snapshot.as_ref().filter(|path| path.exists()) {
+        match server.load_snapshot(path) {
+            Ok(restored) => log::info!("Restored {} seeds from {}", restored, path.display()),
+            Err(e) => log::warn!("Ignoring snapshot {}: {}", path.display(), e),
+        }
+    }
+    server.preprocess();
+
+    let server = Arc::new(server);
+    let preprocess_worker = if config.worker { Some(server.start_worker()?) } else { None };
+
+    let listener = TcpListener::bind(&config.listen)?;
+
+    // Wake up the accept loop on SIGTERM/SIGINT by connecting to ourselves.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let mut wakeup = listener.local_addr()?;
+    if wakeup.ip().is_unspecified() {
+        wakeup.set_ip(match wakeup {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+
+    {
+        let shutdown = shutdown.clone();
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                shutdown.store(true, Ordering::SeqCst);
+                let _ = TcpStream::connect(wakeup);
+            }
+        });
+    }
+
+    log::info!(
+        "Server {}/{} listening on {} (parameter fingerprint {:#018x})",
+        config.id.unwrap(), params.servers(), listener.local_addr()?, params.fingerprint()
+    );
+
+    let timeout = Duration::from_secs(config.timeout);
+
+    // Clients keep their connection open between lookups, so each one gets
+    // its own thread. Idle clients can't hold up anyone else that way.
+    let mut connections: Vec<thread::JoinHandle<()>> = Vec::new();
+
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        connections.retain(|connection| !connection.is_finished());
+        if connections.len() >= config.max_connections {
+            reject_connection(stream, timeout);
+            continue;
+        }
+
+        let server = server.clone();
+        connections.push(thread::spawn(move || handle_connection(&server, stream, timeout)));
+    }
+
+    log::info!("Shutting down, finishing open connections...");
+
+    for connection in connections {
+        connection.join().map_err(|_| "connection handler panicked")?;
+    }
+
+    if let Some(worker) = preprocess_worker {
+        worker.stop();
+    }
+
+    log::info!("Evicted {} seeds that were never queried", server.evicted());
+
+    if let Some(path) = config.snapshot.as_ref() {
+        server.save_snapshot(path)?;
+        log::info!("Saved seeds to {}", path.display());
+    }
+
+    log::info!("Bye.");
+
+    Ok(())
+}
+
+fn main() {
+    log::set_logger(&StderrLogger).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(config) {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,19 +1,36 @@
 //! Methods for making RAID-PIR queries and combining the responses.
 
-use std::ops::BitXorAssign;
-
 use bitvec::prelude::*;
 use rayon::prelude::*;
 
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::types::RaidPirElement;
 use crate::util::*;
 
+/**
+ * Query with its random part already expanded from the servers' seeds, see
+ * [RaidPirClient::prepare].
+ */
+#[derive(Debug)]
+pub struct PreparedQuery {
+    seeds: Vec<u128>,
+    query: BitVec<Lsb0,u8>,
+}
+
+impl PreparedQuery {
+    /**
+     * Seeds this query was expanded from, in order of server id.
+     */
+    pub fn seeds(&self) -> &[u128] {
+        &self.seeds
+    }
+}
+
 /// RaidPir client.
 #[derive(Debug)]
 pub struct RaidPirClient {
-    blocks: usize,
-    blocks_padded: usize,
-    servers: usize,
-    redundancy: usize,
+    params: RaidPirParams,
 }
 
 impl RaidPirClient {
@@ -22,32 +39,20 @@ impl RaidPirClient {
      *
      * ```
      * use raidpir::client::RaidPirClient;
+     * use raidpir::params::RaidPirParams;
      *
-     * let client = RaidPirClient::new(12, 4, 3);
+     * let client = RaidPirClient::new(RaidPirParams::new(12, 4, 3, 1).unwrap());
      * ```
      */
-    pub fn new(blocks: usize, servers: usize, redundancy: usize) -> Self {
-        // TODO: move to param type?
-
-        // blocks per server has to be a multiple of the size of usize to make
-        // the math easier/faster. Since we don't know whether the server is 32
-        // or 64 bit, assume 64 bit.
-        let blocks_padded = if blocks % (servers * 8) == 0 {
-            blocks
-        } else {
-            blocks + servers * 8 - (blocks % (servers * 8))
-        };
-
-        assert!(blocks_padded % servers == 0);
-        assert!((blocks_padded / servers) % 8 == 0);
-        assert!(redundancy >= 2 && redundancy <= servers);
-
-        Self {
-            blocks,
-            blocks_padded,
-            servers,
-            redundancy,
-        }
+    pub fn new(params: RaidPirParams) -> Self {
+        Self { params }
+    }
+
+    /**
+     * Parameters this client was built from.
+     */
+    pub fn params(&self) -> &RaidPirParams {
+        &self.params
     }
 
     /**
@@ -55,27 +60,42 @@ impl RaidPirClient {
      *
      * ```
      * use raidpir::client::RaidPirClient;
+     * use raidpir::params::RaidPirParams;
      *
-     * let client = RaidPirClient::new(12, 4, 3);
-     * let queries = client.query(3, &vec![0, 12, 4, 8]);
+     * let client = RaidPirClient::new(RaidPirParams::new(12, 4, 3, 1).unwrap());
+     * let queries = client.query(3, &vec![0, 12, 4, 8]).unwrap();
      *
      * assert_eq!(queries.len(), 4);
      * assert_eq!(queries[0].len(), 8);
      * ```
      */
-    pub fn query(&self, index: usize, seeds: &Vec<u128>) -> Vec<BitVec::<Lsb0,u8>> {
-        assert!(index < self.blocks);
-        assert!(seeds.len() == self.servers);
+    pub fn query(&self, index: usize, seeds: &[u128]) -> Result<Vec<BitVec::<Lsb0,u8>>> {
+        self.check_index(index)?;
+        self.query_prepared(index, self.prepare(seeds)?)
+    }
+
+    /**
+     * Expand the random part of a query from the given seeds, ahead of
+     * knowing the index.
+     *
+     * This is the expensive part of calculating a query, so it can be done
+     * as soon as the seeds are known. See [RaidPirClient::query_prepared].
+     */
+    pub fn prepare(&self, seeds: &[u128]) -> Result<PreparedQuery> {
+        let params = &self.params;
+
+        if seeds.len() != params.servers() {
+            return Err(RaidPirError::ServerCount { expected: params.servers(), actual: seeds.len() });
+        }
 
         let mut query: BitVec<Lsb0,u8> = BitVec::new();
-        query.resize(self.blocks_padded, false);
-        query.set(index, true);
+        query.resize(params.blocks_padded(), false);
 
-        let blocks_per_server = self.blocks_padded / self.servers;
+        let blocks_per_server = params.blocks_per_server();
 
         let random_bits: Vec<BitVec<Lsb0,u8>> = seeds
             .par_iter()
-            .map(|s| rand_bitvec(*s, blocks_per_server * (self.redundancy - 1)))
+            .map(|s| rand_bitvec(*s, blocks_per_server * (params.redundancy() - 1)))
             .collect();
 
         // BitSlice's as_raw_slice methods only cover the completely covered
@@ -96,25 +116,81 @@ impl RaidPirClient {
             xor_into_slice(query_slice, random.as_raw_slice());
         }
 
+        Ok(PreparedQuery { seeds: seeds.to_vec(), query })
+    }
+
+    /**
+     * Calculate query for the given index from a prepared query, which only
+     * leaves setting a single bit.
+     *
+     * The prepared query is consumed, as using the same seeds for two
+     * queries would reveal their difference to the servers.
+     *
+     * ```
+     * use raidpir::client::RaidPirClient;
+     * use raidpir::params::RaidPirParams;
+     *
+     * let client = RaidPirClient::new(RaidPirParams::new(12, 4, 3, 1).unwrap());
+     * let seeds = vec![0, 12, 4, 8];
+     *
+     * let prepared = client.prepare(&seeds).unwrap();
+     *
+     * assert_eq!(client.query_prepared(3, prepared).unwrap(), client.query(3, &seeds).unwrap());
+     * ```
+     */
+    pub fn query_prepared(&self, index: usize, prepared: PreparedQuery) -> Result<Vec<BitVec::<Lsb0,u8>>> {
+        self.check_index(index)?;
+
+        if prepared.query.len() != self.params.blocks_padded() {
+            return Err(RaidPirError::InvalidParams(format!(
+                "prepared query has {} bits, expected {}",
+                prepared.query.len(), self.params.blocks_padded()
+            )));
+        }
+
+        let blocks_per_server = self.params.blocks_per_server();
+
         // split query into server chunks
-        query
-            .into_vec()
+        let mut queries: Vec<BitVec<Lsb0,u8>> = prepared.query
+            .as_slice()
             .chunks(blocks_per_server / 8)
             .map(|x| BitVec::from_vec(x.to_vec()))
-            .collect()
+            .collect();
+
+        let chunk = &mut queries[index / blocks_per_server];
+        let bit = !chunk[index % blocks_per_server];
+        chunk.set(index % blocks_per_server, bit);
+
+        Ok(queries)
+    }
+
+    fn check_index(&self, index: usize) -> Result<()> {
+        if index >= self.params.blocks() {
+            return Err(RaidPirError::IndexOutOfRange { index, blocks: self.params.blocks() });
+        }
+
+        Ok(())
     }
 
     /**
      * Combine responses from servers to calculate queried element.
      */
-    pub fn combine<T: Clone + Default + BitXorAssign>(&self, responses: Vec<T>) -> T {
-        assert!(responses.len() == self.servers);
+    pub fn combine<T: RaidPirElement>(&self, responses: Vec<T>) -> Result<T> {
+        if responses.len() != self.params.servers() {
+            return Err(RaidPirError::ServerCount { expected: self.params.servers(), actual: responses.len() });
+        }
+
+        let size = self.params.element_size();
+        if let Some(x) = responses.iter().find(|x| x.element_size() != size) {
+            return Err(RaidPirError::ElementSize { expected: size, actual: x.element_size() });
+        }
 
-        let mut data = responses[0].clone();
-        for i in 1..responses.len() {
-            data ^= responses[i].clone();
+        let mut responses = responses.into_iter();
+        let mut data = responses.next().unwrap();
+        for response in responses {
+            data ^= response;
         }
 
-        data
+        Ok(data)
     }
 }
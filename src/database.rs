@@ -0,0 +1,198 @@
+//! On-disk database format.
+//!
+//! A database file consists of a fixed-size header followed by all records,
+//! each exactly `element_size` bytes long:
+//!
+//! ```text
+//! +------------+--------------+-------------------+-------------+----------+---------+
+//! | magic: [8] | version: u64 | element_size: u64 | blocks: u64 | reserved | records |
+//! +------------+--------------+-------------------+-------------+----------+---------+
+//! ```
+//!
+//! All integers are little-endian, and the header is padded to
+//! [HEADER_SIZE] bytes.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::error::{RaidPirError, Result};
+use crate::storage::RaidPirDatabase;
+use crate::types::RaidPirData;
+
+/// Magic bytes at the start of every database file.
+pub const MAGIC: &[u8; 8] = b"RAIDPIR\0";
+
+/// Version of the database file format.
+pub const DATABASE_VERSION: u64 = 1;
+
+/// Size of the file header in bytes.
+pub const HEADER_SIZE: usize = 64;
+
+/**
+ * Header of a database file.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatabaseHeader {
+    /// Size of each record in bytes
+    pub element_size: usize,
+    /// Number of records
+    pub blocks: usize,
+}
+
+impl DatabaseHeader {
+    /**
+     * Encode header, including magic and padding.
+     */
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&DATABASE_VERSION.to_le_bytes());
+        header[16..24].copy_from_slice(&(self.element_size as u64).to_le_bytes());
+        header[24..32].copy_from_slice(&(self.blocks as u64).to_le_bytes());
+        header
+    }
+
+    /**
+     * Decode and validate header.
+     */
+    pub fn from_bytes(header: &[u8]) -> Result<Self> {
+        if header.len() < HEADER_SIZE || &header[0..8] != MAGIC {
+            return Err(RaidPirError::InvalidDatabase("not a RAID-PIR database".to_string()));
+        }
+
+        let field = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+
+        let version = field(8);
+        if version != DATABASE_VERSION {
+            return Err(RaidPirError::InvalidDatabase(format!(
+                "unsupported database version {}",
+                version
+            )));
+        }
+
+        let element_size = field(16) as usize;
+        if element_size == 0 {
+            return Err(RaidPirError::InvalidDatabase("element size must not be 0".to_string()));
+        }
+
+        Ok(Self {
+            element_size,
+            blocks: field(24) as usize,
+        })
+    }
+
+    /**
+     * Expected size of the whole file in bytes. Fails if it doesn't fit into
+     * a u64, which no file does.
+     */
+    pub fn file_size(&self) -> Result<u64> {
+        (self.element_size as u64)
+            .checked_mul(self.blocks as u64)
+            .and_then(|size| size.checked_add(HEADER_SIZE as u64))
+            .ok_or_else(|| {
+                RaidPirError::InvalidDatabase(format!(
+                    "{} records of {} bytes overflow the file size",
+                    self.blocks, self.element_size
+                ))
+            })
+    }
+}
+
+/**
+ * Read a database file into memory.
+ */
+pub fn read_database<P: AsRef<Path>>(path: P) -> Result<Vec<RaidPirData>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let header = DatabaseHeader::from_bytes(&header)?;
+
+    let expected = header.file_size()?;
+    if len != expected {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "file has {} bytes, expected {} for {} records of {} bytes",
+            len, expected, header.blocks, header.element_size
+        )));
+    }
+
+    (0..header.blocks)
+        .map(|_| {
+            let mut buffer = vec![0; header.element_size];
+            reader.read_exact(&mut buffer)?;
+            Ok(RaidPirData::new(buffer))
+        })
+        .collect()
+}
+
+/**
+ * Read a database file into a single contiguous buffer, see
+ * [RaidPirDatabase].
+ */
+pub fn read_contiguous_database<P: AsRef<Path>>(path: P) -> Result<RaidPirDatabase> {
+    let data = std::fs::read(path)?;
+    let header = DatabaseHeader::from_bytes(&data)?;
+
+    let expected = header.file_size()?;
+    if data.len() as u64 != expected {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "file has {} bytes, expected {} for {} records of {} bytes",
+            data.len(), expected, header.blocks, header.element_size
+        )));
+    }
+
+    RaidPirDatabase::from_bytes(header.element_size, &data[HEADER_SIZE..])
+}
+
+/**
+ * Read a headerless file consisting only of records of the given size.
+ */
+pub fn read_raw_database<P: AsRef<Path>>(path: P, element_size: usize) -> Result<Vec<RaidPirData>> {
+    if element_size == 0 {
+        return Err(RaidPirError::InvalidDatabase("element size must not be 0".to_string()));
+    }
+
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    if data.len() % element_size != 0 {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "file size {} is not a multiple of element size {}",
+            data.len(), element_size
+        )));
+    }
+
+    Ok(data
+        .chunks(element_size)
+        .map(|x| RaidPirData::new(x.to_vec()))
+        .collect())
+}
+
+/**
+ * Write records to a database file. All records need to have the same size.
+ */
+pub fn write_database<P: AsRef<Path>>(path: P, db: &[RaidPirData]) -> Result<()> {
+    let element_size = db.first().map(|x| x.as_slice().len()).unwrap_or(0);
+    if element_size == 0 {
+        return Err(RaidPirError::InvalidDatabase("element size must not be 0".to_string()));
+    }
+
+    if let Some(x) = db.iter().find(|x| x.as_slice().len() != element_size) {
+        return Err(RaidPirError::ElementSize { expected: element_size, actual: x.as_slice().len() });
+    }
+
+    let header = DatabaseHeader { element_size, blocks: db.len() };
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&header.to_bytes())?;
+    for x in db.iter() {
+        writer.write_all(x.as_slice())?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
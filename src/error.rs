@@ -0,0 +1,132 @@
+//! Error type for fallible RAID-PIR operations.
+
+use std::fmt;
+
+/// Errors returned by RAID-PIR clients and servers.
+#[derive(Debug)]
+pub enum RaidPirError {
+    /// The given combination of blocks, servers and redundancy is not valid.
+    InvalidParams(String),
+    /// The requested index lies outside of the (unpadded) database.
+    IndexOutOfRange {
+        /// Requested index
+        index: usize,
+        /// Number of blocks in the database
+        blocks: usize,
+    },
+    /// The seed was not handed out by this server, or has already been used.
+    UnknownSeed(u128),
+    /// The query does not have the expected number of bits.
+    QueryLength {
+        /// Expected number of bits
+        expected: usize,
+        /// Actual number of bits
+        actual: usize,
+    },
+    /// The number of seeds or responses does not match the number of servers.
+    ServerCount {
+        /// Number of servers
+        expected: usize,
+        /// Number of seeds or responses given
+        actual: usize,
+    },
+    /// No servers were given to a client.
+    NoServers,
+    /// Database elements or responses do not all have the same size.
+    ElementSize {
+        /// Size of the first element
+        expected: usize,
+        /// Size of the offending element
+        actual: usize,
+    },
+    /// The other side was built from different parameters.
+    ParamsMismatch {
+        /// Our parameter fingerprint
+        expected: u64,
+        /// The other side's parameter fingerprint
+        actual: u64,
+    },
+    /// A database file is malformed or does not match the expected format.
+    InvalidDatabase(String),
+    /// A snapshot file is malformed or belongs to a different database.
+    InvalidSnapshot(String),
+    /// The database or its tables can't be modified, e.g. because they are
+    /// memory-mapped or shared with other servers.
+    ReadOnly(String),
+    /// A malformed or unexpected protocol message was received.
+    Protocol(String),
+    /// The other side reported an error.
+    Remote(String),
+    /// Communication with a specific server failed, or it misbehaved.
+    Server {
+        /// Id of the server
+        server: usize,
+        /// What went wrong
+        error: Box<RaidPirError>,
+    },
+    /// Reading from or writing to the underlying connection failed.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for RaidPirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParams(msg) => write!(f, "invalid parameters: {}", msg),
+            Self::IndexOutOfRange { index, blocks } => {
+                write!(f, "index {} out of range for {} blocks", index, blocks)
+            }
+            Self::UnknownSeed(seed) => write!(f, "unknown or already used seed {:#034x}", seed),
+            Self::QueryLength { expected, actual } => {
+                write!(f, "query has {} bits, expected {}", actual, expected)
+            }
+            Self::ServerCount { expected, actual } => {
+                write!(f, "got {} seeds/responses for {} servers", actual, expected)
+            }
+            Self::NoServers => write!(f, "no servers given"),
+            Self::ElementSize { expected, actual } => {
+                write!(f, "element has size {}, expected {}", actual, expected)
+            }
+            Self::ParamsMismatch { expected, actual } => {
+                write!(f, "parameter fingerprint {:#018x} does not match ours ({:#018x})", actual, expected)
+            }
+            Self::InvalidDatabase(msg) => write!(f, "invalid database: {}", msg),
+            Self::InvalidSnapshot(msg) => write!(f, "invalid snapshot: {}", msg),
+            Self::ReadOnly(msg) => write!(f, "read-only: {}", msg),
+            Self::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Self::Remote(msg) => write!(f, "remote error: {}", msg),
+            Self::Server { server, error } => write!(f, "server {}: {}", server, error),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl RaidPirError {
+    /**
+     * Attribute this error to the server with the given id.
+     */
+    pub fn at_server(self, server: usize) -> Self {
+        match self {
+            Self::Server { .. } => self,
+            error => Self::Server { server, error: Box::new(error) },
+        }
+    }
+}
+
+impl std::error::Error for RaidPirError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Server { error, .. } => Some(error.as_ref()),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RaidPirError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Result type with [RaidPirError] as the error.
+pub type Result<T> = std::result::Result<T, RaidPirError>;
@@ -1,4 +1,4 @@
-#![forbid(unsafe_code)]
+#![deny(unsafe_code)]
 #![warn(missing_docs)]
 
 /*!
@@ -6,9 +6,30 @@
  * described by Günther et al.
  *
  * Should be considered academic and not used for production.
+ *
+ * Databases and Four-Russians tables can be memory-mapped, see
+ * [storage::MappedDatabase] and [russians::open_tables]. Mapping a file is
+ * the only unsafe code in the crate: a mapped file must not be modified or
+ * truncated (e.g. by another process) while it is in use, or reads are
+ * undefined behaviour and truncation kills the process with SIGBUS.
  */
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
 pub mod client;
+pub mod database;
+pub mod error;
+pub mod params;
+pub mod pool;
+pub mod protocol;
+pub mod russians;
+pub mod seeds;
 pub mod server;
+pub mod service;
+pub mod session;
+pub mod snapshot;
+pub mod storage;
+pub mod store;
+pub mod transport;
 pub mod types;
 pub mod util;
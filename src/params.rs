@@ -0,0 +1,187 @@
+//! Parameters shared between RAID-PIR clients and servers.
+
+use std::ops::Range;
+
+use crate::error::{RaidPirError, Result};
+use crate::util::{fnv1a, FNV_OFFSET};
+
+/// Version of the parameter format. Changes whenever the padding or query
+/// layout changes in an incompatible way.
+pub const FORMAT_VERSION: u16 = 1;
+
+/**
+ * Validated set of parameters that clients and servers are built from.
+ *
+ * Clients and servers that were built from different parameters will produce
+ * garbage instead of the queried element, so both sides should compare
+ * [RaidPirParams::fingerprint]s before exchanging queries.
+ *
+ * ```
+ * use raidpir::params::RaidPirParams;
+ *
+ * let params = RaidPirParams::new(12, 4, 3, 1).unwrap();
+ *
+ * assert_eq!(params.blocks_padded(), 32);
+ * assert_eq!(params.blocks_per_server(), 8);
+ * assert_ne!(params.fingerprint(), RaidPirParams::new(12, 4, 2, 1).unwrap().fingerprint());
+ * ```
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RaidPirParams {
+    blocks: usize,
+    blocks_padded: usize,
+    servers: usize,
+    redundancy: usize,
+    element_size: usize,
+    version: u16,
+}
+
+impl RaidPirParams {
+    /**
+     * Validate parameters and calculate the padded database size.
+     */
+    pub fn new(blocks: usize, servers: usize, redundancy: usize, element_size: usize) -> Result<Self> {
+        if blocks == 0 || servers == 0 {
+            return Err(RaidPirError::InvalidParams(format!(
+                "need at least one block and server, got {} blocks and {} servers",
+                blocks, servers
+            )));
+        }
+
+        if redundancy < 2 || redundancy > servers {
+            return Err(RaidPirError::InvalidParams(format!(
+                "redundancy {} not in 2..={}",
+                redundancy, servers
+            )));
+        }
+
+        if element_size == 0 {
+            return Err(RaidPirError::InvalidParams("element size must not be 0".to_string()));
+        }
+
+        // blocks per server has to be a multiple of the size of usize to make
+        // the math easier/faster. Since we don't know whether the server is 32
+        // or 64 bit, assume 64 bit.
+        let overflow = || {
+            RaidPirError::InvalidParams(format!("{} blocks for {} servers overflow when padded", blocks, servers))
+        };
+        let unit = servers.checked_mul(8).ok_or_else(overflow)?;
+        let blocks_padded = match blocks % unit {
+            0 => blocks,
+            rest => blocks.checked_add(unit - rest).ok_or_else(overflow)?,
+        };
+
+        Ok(Self {
+            blocks,
+            blocks_padded,
+            servers,
+            redundancy,
+            element_size,
+            version: FORMAT_VERSION,
+        })
+    }
+
+    /// Number of blocks in the unpadded database.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// Number of blocks after padding to a multiple of `servers * 8`.
+    pub fn blocks_padded(&self) -> usize {
+        self.blocks_padded
+    }
+
+    /// Number of blocks in each server's chunk.
+    pub fn blocks_per_server(&self) -> usize {
+        self.blocks_padded / self.servers
+    }
+
+    /// Number of blocks each server needs, including padding: its own chunk
+    /// and the `redundancy - 1` chunks after it.
+    pub fn window_len(&self) -> usize {
+        self.redundancy * self.blocks_per_server()
+    }
+
+    /**
+     * Ranges of the unpadded database that server `id` needs, in the order
+     * the server accesses them.
+     *
+     * A server only ever touches the [RaidPirParams::window_len] blocks
+     * starting at its own chunk, wrapping around at the end of the padded
+     * database. Padding is left out, so there are at most two ranges.
+     *
+     * ```
+     * use raidpir::params::RaidPirParams;
+     *
+     * let params = RaidPirParams::new(12, 4, 3, 1).unwrap();
+     *
+     * assert_eq!(params.window(0), vec![0..12]);
+     * assert_eq!(params.window(2), vec![0..8]);
+     * assert_eq!(params.window(3), vec![0..12]);
+     * ```
+     */
+    pub fn window(&self, id: usize) -> Vec<Range<usize>> {
+        let start = id * self.blocks_per_server();
+        let end = start + self.window_len();
+
+        let ranges = match end > self.blocks_padded {
+            true => [start..self.blocks, 0..(end - self.blocks_padded).min(self.blocks)],
+            false => [start..end.min(self.blocks), 0..0],
+        };
+
+        ranges.iter().filter(|x| x.start < x.end).cloned().collect()
+    }
+
+    /// Number of servers.
+    pub fn servers(&self) -> usize {
+        self.servers
+    }
+
+    /// Number of servers that each database chunk is distributed to.
+    pub fn redundancy(&self) -> usize {
+        self.redundancy
+    }
+
+    /// Size of each database element in bytes.
+    pub fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    /// Parameter format version, see [FORMAT_VERSION].
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /**
+     * Stable fingerprint of these parameters.
+     *
+     * This is an FNV-1a hash over a fixed little-endian encoding, so it does
+     * not depend on platform or compiler version.
+     */
+    pub fn fingerprint(&self) -> u64 {
+        let fields = [
+            self.version as u64,
+            self.blocks as u64,
+            self.blocks_padded as u64,
+            self.servers as u64,
+            self.redundancy as u64,
+            self.element_size as u64,
+        ];
+
+        fields.iter().fold(FNV_OFFSET, |hash, x| fnv1a(hash, &x.to_le_bytes()))
+    }
+
+    /**
+     * Check that the other side was built from the same parameters.
+     */
+    pub fn check_fingerprint(&self, fingerprint: u64) -> Result<()> {
+        if fingerprint != self.fingerprint() {
+            return Err(RaidPirError::ParamsMismatch {
+                expected: self.fingerprint(),
+                actual: fingerprint,
+            });
+        }
+
+        Ok(())
+    }
+}
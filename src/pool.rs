@@ -0,0 +1,134 @@
+//! Client-side pool of prefetched seeds.
+//!
+//! Seeds are fetched from every server in batches ahead of time and their
+//! random bits are expanded right away (see [RaidPirClient::prepare]), so the
+//! online part of a lookup is only setting the queried bit and a single
+//! query/response round trip.
+//!
+//! [RaidPirClient::prepare]: crate::client::RaidPirClient::prepare
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::client::PreparedQuery;
+use crate::error::{RaidPirError, Result};
+use crate::service::{fetch_prepared, Connection, MAX_OUTSTANDING_SEEDS};
+use crate::transport::Transport;
+
+/// Default time after which prefetched seeds are discarded.
+pub const DEFAULT_SEED_TTL: Duration = Duration::from_secs(300);
+
+/**
+ * Pool of prepared queries, expanded from seeds fetched ahead of time.
+ *
+ * Seeds are only valid on the connections they were granted on, so the pool
+ * has to be cleared whenever the connections are replaced. Seeds older than
+ * the pool's time to live are discarded, as servers may have forgotten them
+ * by then.
+ */
+#[derive(Debug)]
+pub struct SeedPool {
+    entries: VecDeque<(Instant, PreparedQuery)>,
+    ttl: Duration,
+}
+
+impl Default for SeedPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED_TTL)
+    }
+}
+
+impl SeedPool {
+    /**
+     * Create an empty pool, discarding seeds after `ttl`.
+     */
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            ttl,
+        }
+    }
+
+    /**
+     * Time after which seeds are discarded.
+     */
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /**
+     * Set time after which seeds are discarded.
+     */
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /**
+     * Number of prepared queries that have not expired yet.
+     */
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|(fetched, _)| fetched.elapsed() < self.ttl).count()
+    }
+
+    /**
+     * Whether there are no prepared queries left that have not expired.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * Discard all prepared queries, e.g. after reconnecting.
+     */
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /**
+     * Fetch seeds from all servers until the pool holds `count` prepared
+     * queries.
+     *
+     * As servers limit the number of outstanding seeds per connection,
+     * `count` can be at most [MAX_OUTSTANDING_SEEDS].
+     */
+    pub fn fill<C: Transport + Send>(&mut self, connections: &mut [Connection<C>], count: usize) -> Result<()> {
+        if count > MAX_OUTSTANDING_SEEDS {
+            return Err(RaidPirError::InvalidParams(format!(
+                "cannot prefetch more than {} seeds",
+                MAX_OUTSTANDING_SEEDS
+            )));
+        }
+
+        self.expire();
+        let missing = count.saturating_sub(self.entries.len());
+        if missing == 0 {
+            return Ok(());
+        }
+
+        let prepared = fetch_prepared(connections, missing)?;
+        let fetched = Instant::now();
+        self.entries.extend(prepared.into_iter().map(|p| (fetched, p)));
+
+        Ok(())
+    }
+
+    /**
+     * Take up to `count` prepared queries, oldest first.
+     */
+    pub fn take(&mut self, count: usize) -> Vec<PreparedQuery> {
+        self.expire();
+
+        let count = count.min(self.entries.len());
+        self.entries.drain(..count).map(|(_, p)| p).collect()
+    }
+
+    fn expire(&mut self) {
+        while let Some((fetched, _)) = self.entries.front() {
+            if fetched.elapsed() < self.ttl {
+                break;
+            }
+
+            self.entries.pop_front();
+        }
+    }
+}
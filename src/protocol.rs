@@ -0,0 +1,338 @@
+//! Versioned wire protocol for exchanging parameters, seeds, queries and
+//! responses between RAID-PIR clients and servers.
+//!
+//! Every message is sent as a single frame:
+//!
+//! ```text
+//! +----------------+--------------+-----------+-----------------+
+//! | length: u32 LE | version: u8  | type: u8  | payload         |
+//! +----------------+--------------+-----------+-----------------+
+//! ```
+//!
+//! where `length` covers version, type and payload. All integers are
+//! little-endian.
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{ErrorKind, Read, Write};
+
+use bitvec::prelude::*;
+
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+
+/// Version of the wire protocol. Frames with a different version are rejected.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Maximum accepted frame length, to protect against malicious length fields.
+pub const MAX_FRAME_SIZE: usize = 1 << 30;
+
+/// Buffer size frame bodies are read into at first. Larger bodies grow the
+/// buffer as they arrive, so a length prefix alone can't make the other side
+/// allocate up to [MAX_FRAME_SIZE].
+const INITIAL_FRAME_BUFFER: usize = 64 << 10;
+
+const TYPE_HELLO: u8 = 1;
+const TYPE_SEED_GRANT: u8 = 2;
+const TYPE_QUERY: u8 = 3;
+const TYPE_RESPONSE: u8 = 4;
+const TYPE_ERROR: u8 = 5;
+const TYPE_SEED_REQUEST: u8 = 6;
+
+/**
+ * Messages exchanged between client and server.
+ *
+ * ```
+ * use raidpir::protocol::{decode, encode, Message};
+ *
+ * let frame = encode(&Message::SeedGrant(1234));
+ *
+ * assert_eq!(decode(&frame).unwrap(), Message::SeedGrant(1234));
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Parameters the server was built from, sent when a connection is opened.
+    Hello(RaidPirParams),
+    /// Seed handed out by the server for a single query.
+    SeedGrant(u128),
+    /// Query for this server's chunk, using a previously granted seed.
+    Query {
+        /// Seed granted by this server
+        seed: u128,
+        /// Query bits for this server's chunk
+        query: BitVec<Lsb0, u8>,
+    },
+    /// Response to a query, as the raw bytes of the database element.
+    Response(Vec<u8>),
+    /// Error reported by the other side.
+    Error(String),
+    /// Request for more seeds on an open connection, answered with as many
+    /// [Message::SeedGrant]s.
+    SeedRequest(u32),
+}
+
+impl Message {
+    /**
+     * Name of the message type, for error messages and logging.
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Hello(_) => "Hello",
+            Self::SeedGrant(_) => "SeedGrant",
+            Self::Query { .. } => "Query",
+            Self::Response(_) => "Response",
+            Self::Error(_) => "Error",
+            Self::SeedRequest(_) => "SeedRequest",
+        }
+    }
+
+    fn message_type(&self) -> u8 {
+        match self {
+            Self::Hello(_) => TYPE_HELLO,
+            Self::SeedGrant(_) => TYPE_SEED_GRANT,
+            Self::Query { .. } => TYPE_QUERY,
+            Self::Response(_) => TYPE_RESPONSE,
+            Self::Error(_) => TYPE_ERROR,
+            Self::SeedRequest(_) => TYPE_SEED_REQUEST,
+        }
+    }
+
+    fn encode_payload(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Hello(params) => {
+                buf.extend_from_slice(&params.version().to_le_bytes());
+                buf.extend_from_slice(&(params.blocks() as u64).to_le_bytes());
+                buf.extend_from_slice(&(params.servers() as u64).to_le_bytes());
+                buf.extend_from_slice(&(params.redundancy() as u64).to_le_bytes());
+                buf.extend_from_slice(&(params.element_size() as u64).to_le_bytes());
+                buf.extend_from_slice(&params.fingerprint().to_le_bytes());
+            }
+            Self::SeedGrant(seed) => {
+                buf.extend_from_slice(&seed.to_le_bytes());
+            }
+            Self::Query { seed, query } => {
+                buf.extend_from_slice(&seed.to_le_bytes());
+                buf.extend_from_slice(&(query.len() as u64).to_le_bytes());
+                buf.extend_from_slice(query.as_slice());
+            }
+            Self::Response(data) => {
+                buf.extend_from_slice(data);
+            }
+            Self::Error(msg) => {
+                buf.extend_from_slice(msg.as_bytes());
+            }
+            Self::SeedRequest(count) => {
+                buf.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode_payload(message_type: u8, payload: &[u8]) -> Result<Self> {
+        let mut reader = PayloadReader { payload };
+
+        let msg = match message_type {
+            TYPE_HELLO => {
+                let version = reader.u16()?;
+                let blocks = reader.usize()?;
+                let servers = reader.usize()?;
+                let redundancy = reader.usize()?;
+                let element_size = reader.usize()?;
+                let fingerprint = reader.u64()?;
+
+                let params = RaidPirParams::new(blocks, servers, redundancy, element_size)?;
+                if params.version() != version {
+                    return Err(RaidPirError::Protocol(format!(
+                        "unsupported parameter version {}",
+                        version
+                    )));
+                }
+                params.check_fingerprint(fingerprint)?;
+
+                Self::Hello(params)
+            }
+            TYPE_SEED_GRANT => Self::SeedGrant(reader.u128()?),
+            TYPE_QUERY => {
+                let seed = reader.u128()?;
+                let bits = reader.usize()?;
+                let bytes = reader.rest();
+                if bytes.len() != bits.div_ceil(8) {
+                    return Err(RaidPirError::Protocol(format!(
+                        "query of {} bits has {} bytes",
+                        bits, bytes.len()
+                    )));
+                }
+
+                let mut query = BitVec::from_vec(bytes.to_vec());
+                query.truncate(bits);
+
+                Self::Query { seed, query }
+            }
+            TYPE_RESPONSE => Self::Response(reader.rest().to_vec()),
+            TYPE_ERROR => Self::Error(
+                String::from_utf8(reader.rest().to_vec())
+                    .map_err(|_| RaidPirError::Protocol("error message is not UTF-8".to_string()))?,
+            ),
+            TYPE_SEED_REQUEST => Self::SeedRequest(reader.u32()?),
+            t => {
+                return Err(RaidPirError::Protocol(format!("unknown message type {}", t)));
+            }
+        };
+
+        if !reader.payload.is_empty() {
+            return Err(RaidPirError::Protocol(format!(
+                "{} trailing bytes in message of type {}",
+                reader.payload.len(), message_type
+            )));
+        }
+
+        Ok(msg)
+    }
+}
+
+/// Bounds-checked reader for message payloads.
+struct PayloadReader<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.payload.len() < N {
+            return Err(RaidPirError::Protocol("truncated message".to_string()));
+        }
+
+        let (head, tail) = self.payload.split_at(N);
+        self.payload = tail;
+
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(head);
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn u128(&mut self) -> Result<u128> {
+        Ok(u128::from_le_bytes(self.take()?))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let x = self.u64()?;
+        usize::try_from(x).map_err(|_| RaidPirError::Protocol(format!("value {} too large", x)))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.payload)
+    }
+}
+
+/**
+ * Encode a message into a complete frame, including the length prefix.
+ */
+pub fn encode(msg: &Message) -> Vec<u8> {
+    let mut buf = vec![0; 4];
+    buf.push(PROTOCOL_VERSION);
+    buf.push(msg.message_type());
+    msg.encode_payload(&mut buf);
+
+    let len = (buf.len() - 4) as u32;
+    buf[0..4].copy_from_slice(&len.to_le_bytes());
+
+    buf
+}
+
+/**
+ * Decode a complete frame, including the length prefix.
+ */
+pub fn decode(frame: &[u8]) -> Result<Message> {
+    if frame.len() < 4 {
+        return Err(RaidPirError::Protocol("truncated frame".to_string()));
+    }
+
+    let (len, body) = frame.split_at(4);
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    if len != body.len() {
+        return Err(RaidPirError::Protocol(format!(
+            "frame length {} does not match {} bytes received",
+            len, body.len()
+        )));
+    }
+
+    decode_body(body)
+}
+
+/// Decode a frame without its length prefix.
+pub(crate) fn decode_body(body: &[u8]) -> Result<Message> {
+    if body.len() < 2 {
+        return Err(RaidPirError::Protocol("truncated frame".to_string()));
+    }
+
+    if body[0] != PROTOCOL_VERSION {
+        return Err(RaidPirError::Protocol(format!(
+            "unsupported protocol version {}",
+            body[0]
+        )));
+    }
+
+    Message::decode_payload(body[1], &body[2..])
+}
+
+/// Parse and check the length prefix of a frame.
+pub(crate) fn frame_length(prefix: [u8; 4]) -> Result<usize> {
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(RaidPirError::Protocol(format!("frame length {} too large", len)));
+    }
+
+    Ok(len)
+}
+
+/// Empty buffer for a frame body of `len` bytes.
+pub(crate) fn frame_buffer(len: usize) -> Vec<u8> {
+    Vec::with_capacity(len.min(INITIAL_FRAME_BUFFER))
+}
+
+/// Check that a frame body was read completely.
+pub(crate) fn check_body(body: &[u8], len: usize) -> Result<()> {
+    if body.len() != len {
+        return Err(std::io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("frame ended after {} of {} bytes", body.len(), len),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/**
+ * Write a single framed message to the given writer.
+ */
+pub fn write_message<W: Write>(writer: &mut W, msg: &Message) -> Result<()> {
+    writer.write_all(&encode(msg))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/**
+ * Read a single framed message from the given reader.
+ */
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+
+    let len = frame_length(len)?;
+    let mut body = frame_buffer(len);
+    reader.take(len as u64).read_to_end(&mut body)?;
+    check_body(&body, len)?;
+
+    decode_body(&body)
+}
@@ -0,0 +1,460 @@
+//! Four-Russians lookup tables for XORing together database elements.
+//!
+//! The database is split into windows of `window` consecutive elements, and
+//! for each window the XOR of every one of the `2^window` subsets is
+//! precomputed. Selecting elements by a bit vector then takes one lookup per
+//! `window` bits, instead of one XOR per set bit.
+//!
+//! Wider windows mean fewer lookups, but the tables take
+//! `2^window / window` times the memory of the elements they cover, see
+//! [memory_estimate].
+//!
+//! Tables can be saved to a file with [write_tables] and memory-mapped with
+//! [open_tables], instead of building them on every start. A table file
+//! consists of a fixed-size header followed by the tables for the server's
+//! own chunk and then the tables for the preprocessing chunks:
+//!
+//! ```text
+//! +------------+--------------+-------------+-------------------+-------------+----------+-----------------+----------+--------+
+//! | magic: [8] | version: u64 | digest: u64 | element_size: u64 | window: u64 | len: u64 | preprocess: u64 | reserved | tables |
+//! +------------+--------------+-------------+-------------------+-------------+----------+-----------------+----------+--------+
+//! ```
+//!
+//! where `len` and `preprocess` are the number of elements covered by either
+//! set of tables (0 if absent). Each table is `2^window` entries of
+//! `element_size` bytes. All integers are little-endian, and the header is
+//! padded to [HEADER_SIZE] bytes.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::storage::map_file;
+use crate::types::{RaidPirBytes, RaidPirElement};
+
+/// Magic bytes at the start of every table file.
+pub const MAGIC: &[u8; 8] = b"RPIRRUSS";
+
+/// Version of the table file format.
+pub const TABLES_VERSION: u64 = 1;
+
+/// Size of the table file header in bytes.
+pub const HEADER_SIZE: usize = 64;
+
+/// Default window width in bits.
+pub const DEFAULT_WINDOW: usize = 8;
+
+/// Largest supported window width in bits.
+pub const MAX_WINDOW: usize = 16;
+
+/**
+ * Lookup tables for a contiguous range of database elements.
+ */
+#[derive(Debug, Clone)]
+pub struct RussiansTables<T> {
+    window: usize,
+    len: usize,
+    tables: Tables<T>,
+}
+
+#[derive(Debug, Clone)]
+enum Tables<T> {
+    Owned(Vec<Vec<T>>),
+    /// Tables in a memory-mapped file, starting at `offset`.
+    Mapped {
+        map: Arc<Mmap>,
+        offset: usize,
+        element_size: usize,
+        xor: fn(&mut T, &[u8]),
+    },
+}
+
+impl<T: RaidPirElement> RussiansTables<T> {
+    /**
+     * Build tables for the given elements, with windows of `window` bits.
+     * The last window is padded with default elements if needed.
+     *
+     * Windows are built in parallel on the current rayon thread pool.
+     */
+    pub fn new(db: &[T], window: usize) -> Result<Self> {
+        Self::with_progress(db, window, |_, _| {})
+    }
+
+    /**
+     * Like [RussiansTables::new], but calls `progress` with the number of
+     * windows built so far and the total number of windows after each
+     * window. It is called from several threads, so it should be cheap.
+     */
+    pub fn with_progress<F>(db: &[T], window: usize, progress: F) -> Result<Self>
+    where
+        F: Fn(usize, usize) + Sync,
+    {
+        Self::build(db.len(), window, |i| db[i].clone(), progress)
+    }
+
+    /// Build tables for `len` elements, taken from `get`.
+    pub(crate) fn build<G, F>(len: usize, window: usize, get: G, progress: F) -> Result<Self>
+    where
+        G: Fn(usize) -> T + Sync,
+        F: Fn(usize, usize) + Sync,
+    {
+        check_window(window)?;
+
+        let total = len.div_ceil(window);
+        let done = AtomicUsize::new(0);
+
+        let tables = (0..total)
+            .into_par_iter()
+            .map(|i| {
+                let chunk: Vec<T> = (i * window..len.min((i + 1) * window)).map(&get).collect();
+                let table = build_table(&chunk, window);
+                progress(done.fetch_add(1, Ordering::Relaxed) + 1, total);
+                table
+            })
+            .collect();
+
+        Ok(Self {
+            window,
+            len,
+            tables: Tables::Owned(tables),
+        })
+    }
+
+    /**
+     * Window width in bits.
+     */
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /**
+     * Number of elements covered.
+     */
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /**
+     * Whether no elements are covered.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * Whether the tables are memory-mapped from a file, see [open_tables].
+     */
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.tables, Tables::Mapped { .. })
+    }
+
+    /// Number of windows, i.e. tables.
+    fn count(&self) -> usize {
+        self.len.div_ceil(self.window)
+    }
+
+    /**
+     * XOR the elements selected by the bits in `bytes` (least significant bit
+     * first) into `answer`.
+     */
+    pub fn xor_into(&self, answer: &mut T, bytes: &[u8]) {
+        match &self.tables {
+            Tables::Owned(tables) => self.for_each_entry(bytes, |i, q| *answer ^= &tables[i][q]),
+            Tables::Mapped { map, offset, element_size, xor } => {
+                let entries = 1 << self.window;
+                self.for_each_entry(bytes, |i, q| {
+                    let start = offset + (i * entries + q) * element_size;
+                    xor(answer, &map[start..start + element_size]);
+                });
+            }
+        }
+    }
+
+    /**
+     * XOR `delta` into every entry that includes the element at `index`,
+     * e.g. after that element was XORed with `delta`. Memory-mapped tables
+     * can't be updated.
+     */
+    pub fn update(&mut self, index: usize, delta: &T) -> Result<()> {
+        if index >= self.len {
+            return Err(RaidPirError::IndexOutOfRange { index, blocks: self.len });
+        }
+
+        let tables = match &mut self.tables {
+            Tables::Owned(tables) => tables,
+            Tables::Mapped { .. } => {
+                return Err(RaidPirError::ReadOnly("Four-Russians tables are memory-mapped".to_string()))
+            }
+        };
+
+        // Entry q includes element i of the window iff bit i of q is set.
+        let bit = 1 << (index % self.window);
+        tables[index / self.window]
+            .iter_mut()
+            .enumerate()
+            .filter(|(q, _)| q & bit != 0)
+            .for_each(|(_, x)| *x ^= delta);
+
+        Ok(())
+    }
+
+    /// Call `f` with the table and entry selected by every window of bits.
+    fn for_each_entry<F: FnMut(usize, usize)>(&self, bytes: &[u8], mut f: F) {
+        let count = self.count();
+
+        if self.window == 8 {
+            bytes.iter().take(count).enumerate().for_each(|(i, q)| f(i, *q as usize));
+            return;
+        }
+
+        let bits = bytes.len() * 8;
+        (0..count)
+            .take_while(|i| i * self.window < bits)
+            .for_each(|i| f(i, window_index(bytes, i * self.window, self.window)));
+    }
+}
+
+impl<T: RaidPirBytes> RussiansTables<T> {
+    /// Write all entries, padded to `element_size` bytes each.
+    fn write<W: Write>(&self, writer: &mut W, element_size: usize) -> Result<()> {
+        match &self.tables {
+            Tables::Owned(tables) => {
+                let mut buffer = Vec::with_capacity(element_size);
+                for entry in tables.iter().flatten() {
+                    buffer.clear();
+                    entry.write_bytes(&mut buffer);
+                    // Entries without any elements may be empty.
+                    buffer.resize(element_size, 0);
+                    writer.write_all(&buffer)?;
+                }
+            }
+            Tables::Mapped { map, offset, .. } => {
+                let size = self.count() * (1 << self.window) * element_size;
+                writer.write_all(&map[*offset..offset + size])?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Build the table for a single window. Entries are visited in Gray code
+/// order, where each entry differs from the previous one in a single bit, so
+/// every entry takes only one XOR.
+fn build_table<T: RaidPirElement>(chunk: &[T], window: usize) -> Vec<T> {
+    let mut table: Vec<T> = vec![T::default(); 1 << window];
+    let mut current = T::default();
+
+    for i in 1..(1usize << window) {
+        // Gray code i ^ (i >> 1) flips the lowest set bit of i.
+        if let Some(x) = chunk.get(i.trailing_zeros() as usize) {
+            current ^= x;
+        }
+        table[i ^ (i >> 1)] = current.clone();
+    }
+
+    table
+}
+
+/// Read `window` bits starting at bit `pos`, least significant bit first.
+/// Bits beyond the end of `bytes` are 0.
+fn window_index(bytes: &[u8], pos: usize, window: usize) -> usize {
+    let raw = bytes[pos / 8..]
+        .iter()
+        .take(3)
+        .enumerate()
+        .fold(0u32, |raw, (i, b)| raw | (*b as u32) << (8 * i));
+
+    (raw >> (pos % 8)) as usize & ((1 << window) - 1)
+}
+
+pub(crate) fn check_window(window: usize) -> Result<()> {
+    if window == 0 || window > MAX_WINDOW {
+        return Err(RaidPirError::InvalidParams(format!(
+            "Four-Russians window of {} bits not in 1..={}",
+            window, MAX_WINDOW
+        )));
+    }
+
+    Ok(())
+}
+
+/**
+ * Save tables to a file, see [open_tables].
+ *
+ * `digest` identifies the database the tables were built from, e.g.
+ * [crate::server::RaidPirServer::digest]. Both sets of tables need to have
+ * the same window width. The file is replaced atomically.
+ */
+pub fn write_tables<T, P>(
+    path: P,
+    digest: u64,
+    element_size: usize,
+    own: Option<&RussiansTables<T>>,
+    preprocess: Option<&RussiansTables<T>>,
+) -> Result<()>
+where
+    T: RaidPirBytes,
+    P: AsRef<Path>,
+{
+    let window = own.or(preprocess).map(|t| t.window).unwrap_or(DEFAULT_WINDOW);
+    if own.into_iter().chain(preprocess).any(|t| t.window != window) {
+        return Err(RaidPirError::InvalidParams("tables with different window widths".to_string()));
+    }
+
+    let mut header = [0; HEADER_SIZE];
+    header[0..8].copy_from_slice(MAGIC);
+    header[8..16].copy_from_slice(&TABLES_VERSION.to_le_bytes());
+    header[16..24].copy_from_slice(&digest.to_le_bytes());
+    header[24..32].copy_from_slice(&(element_size as u64).to_le_bytes());
+    header[32..40].copy_from_slice(&(window as u64).to_le_bytes());
+    header[40..48].copy_from_slice(&(own.map_or(0, |t| t.len) as u64).to_le_bytes());
+    header[48..56].copy_from_slice(&(preprocess.map_or(0, |t| t.len) as u64).to_le_bytes());
+
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(&header)?;
+        for tables in own.into_iter().chain(preprocess) {
+            tables.write(&mut writer, element_size)?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+/**
+ * Memory-map tables saved with [write_tables].
+ *
+ * Fails unless the file was written for the same `digest` and
+ * `element_size`. Returns the tables for the server's own chunk and for the
+ * preprocessing chunks, either of which may be absent.
+ *
+ * The file must not be modified or truncated while it is mapped, see
+ * [crate::storage::MappedDatabase].
+ */
+#[allow(clippy::type_complexity)]
+pub fn open_tables<T, P>(
+    path: P,
+    digest: u64,
+    element_size: usize,
+) -> Result<(Option<RussiansTables<T>>, Option<RussiansTables<T>>)>
+where
+    T: RaidPirBytes,
+    P: AsRef<Path>,
+{
+    let map = Arc::new(map_file(&File::open(path)?)?);
+
+    if map.len() < HEADER_SIZE || &map[0..8] != MAGIC {
+        return Err(RaidPirError::InvalidDatabase("not a Four-Russians table file".to_string()));
+    }
+
+    let field = |i: usize| u64::from_le_bytes(map[i..i + 8].try_into().unwrap());
+
+    if field(8) != TABLES_VERSION {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "unsupported table file version {}",
+            field(8)
+        )));
+    }
+
+    if field(16) != digest || field(24) != element_size as u64 {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "table file digest {:#018x} does not match database ({:#018x})",
+            field(16), digest
+        )));
+    }
+
+    let window = field(32) as usize;
+    check_window(window)?;
+
+    let (own_len, preprocess_len) = (field(40) as usize, field(48) as usize);
+    let sizes = table_size(own_len, window, element_size).and_then(|own_size| {
+        let total = HEADER_SIZE
+            .checked_add(own_size)?
+            .checked_add(table_size(preprocess_len, window, element_size)?)?;
+        Some((own_size, total))
+    });
+    let (own_size, expected) = sizes.ok_or_else(|| {
+        RaidPirError::InvalidDatabase(format!(
+            "tables for {} and {} records overflow the file size",
+            own_len, preprocess_len
+        ))
+    })?;
+    if map.len() != expected {
+        return Err(RaidPirError::InvalidDatabase(format!(
+            "table file has {} bytes, expected {}",
+            map.len(), expected
+        )));
+    }
+
+    let tables = |len: usize, offset: usize| {
+        (len > 0).then(|| RussiansTables {
+            window,
+            len,
+            tables: Tables::Mapped {
+                map: map.clone(),
+                offset,
+                element_size,
+                xor: T::xor_bytes,
+            },
+        })
+    };
+
+    Ok((tables(own_len, HEADER_SIZE), tables(preprocess_len, HEADER_SIZE + own_size)))
+}
+
+/// Size in bytes of the tables for `len` records, or `None` on overflow.
+fn table_size(len: usize, window: usize, element_size: usize) -> Option<usize> {
+    len.div_ceil(window)
+        .checked_mul(1 << window)?
+        .checked_mul(element_size)
+}
+
+/**
+ * Estimated memory needed for the tables of one server, in bytes, for
+ * windows of `window` bits.
+ *
+ * With `preprocess`, tables also cover the `redundancy - 1` chunks used for
+ * preprocessing, instead of only the server's own chunk. Heap allocations
+ * per element (e.g. for [crate::types::RaidPirData]) are not included.
+ *
+ * ```
+ * use raidpir::params::RaidPirParams;
+ * use raidpir::russians::memory_estimate;
+ *
+ * let params = RaidPirParams::new(1 << 20, 4, 2, 32).unwrap();
+ *
+ * assert_eq!(memory_estimate(&params, 8, false).unwrap(), 256 << 20);
+ * assert_eq!(memory_estimate(&params, 4, false).unwrap(), 32 << 20);
+ * assert_eq!(memory_estimate(&params, 4, true).unwrap(), 64 << 20);
+ * ```
+ */
+pub fn memory_estimate(params: &RaidPirParams, window: usize, preprocess: bool) -> Result<usize> {
+    check_window(window)?;
+
+    let blocks_per_server = params.blocks_per_server();
+    let mut size = table_size(blocks_per_server, window, params.element_size());
+    if preprocess {
+        let preprocess_blocks = (params.redundancy() - 1) * blocks_per_server;
+        size = size.and_then(|size| size.checked_add(table_size(preprocess_blocks, window, params.element_size())?));
+    }
+
+    size.ok_or_else(|| {
+        RaidPirError::InvalidParams(format!("tables with windows of {} bits don't fit into memory", window))
+    })
+}
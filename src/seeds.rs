@@ -0,0 +1,156 @@
+//! Stateless seeds, which the issuing server can recognize without storing
+//! them.
+//!
+//! Each seed is an AES-128 encryption of a block containing a fixed tag, the
+//! time of issue and a counter. Seeds are thus indistinguishable from random
+//! to anyone without the key, and servers sharing the key (e.g. behind a load
+//! balancer, or after a restart) can check that a seed was issued by one of
+//! them, and when. To keep such seeds from being answered more than once,
+//! the issuer remembers redeemed seeds until they expire. This only covers
+//! the seeds redeemed by one process; servers sharing a key also have to
+//! share a [crate::store::SeedStore], which records redeemed seeds for all
+//! of them.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Tag in the upper 32 bits of every plaintext block, "RPIR".
+const TAG: u32 = 0x5250_4952;
+
+/// Tolerated clock difference between servers sharing a key.
+const CLOCK_SKEW: u64 = 60;
+
+/**
+ * Issues and verifies stateless seeds under a secret key.
+ *
+ * ```
+ * use std::time::Duration;
+ *
+ * use raidpir::seeds::SeedIssuer;
+ *
+ * let issuer = SeedIssuer::new([42; 16]);
+ * let seed = issuer.issue();
+ *
+ * assert!(issuer.verify(seed, None));
+ * assert!(!issuer.verify(seed ^ 1, None));
+ * assert!(!SeedIssuer::new([23; 16]).verify(seed, None));
+ *
+ * let ttl = Duration::from_secs(600);
+ * assert!(issuer.redeem(seed, ttl));
+ * assert!(!issuer.redeem(seed, ttl));
+ * ```
+ */
+pub struct SeedIssuer {
+    cipher: Aes128,
+    counter: AtomicU64,
+    /// Redeemed seeds with their time of issue, ordered by it so that
+    /// expired ones can be dropped.
+    redeemed: Mutex<BTreeSet<(u64, u128)>>,
+}
+
+impl fmt::Debug for SeedIssuer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't leak the key into logs.
+        f.debug_struct("SeedIssuer").finish_non_exhaustive()
+    }
+}
+
+impl SeedIssuer {
+    /**
+     * Create an issuer with the given key.
+     *
+     * The counter starts at a random value, so servers sharing a key are
+     * very unlikely to ever issue the same seed.
+     */
+    pub fn new(key: [u8; 16]) -> Self {
+        Self {
+            cipher: Aes128::new(&GenericArray::from(key)),
+            counter: AtomicU64::new(OsRng.next_u64()),
+            redeemed: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /**
+     * Issue a fresh seed.
+     */
+    pub fn issue(&self) -> u128 {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        let issued = u32::try_from(now()).unwrap_or(u32::MAX);
+
+        let plain = ((TAG as u128) << 96) | ((issued as u128) << 64) | counter as u128;
+
+        let mut block = GenericArray::from(plain.to_le_bytes());
+        self.cipher.encrypt_block(&mut block);
+
+        u128::from_le_bytes(block.into())
+    }
+
+    /**
+     * Check that `seed` was issued under this key, and, if `ttl` is given,
+     * not longer than `ttl` ago.
+     */
+    pub fn verify(&self, seed: u128, ttl: Option<Duration>) -> bool {
+        self.issued(seed).is_some_and(|issued| fresh(issued, ttl))
+    }
+
+    /**
+     * Check `seed` like [SeedIssuer::verify], and that it wasn't redeemed
+     * before. Redeemed seeds are remembered until they are older than `ttl`,
+     * from when on they fail verification anyway.
+     */
+    pub fn redeem(&self, seed: u128, ttl: Duration) -> bool {
+        let issued = match self.issued(seed) {
+            Some(issued) if fresh(issued, Some(ttl)) => issued,
+            _ => return false,
+        };
+
+        let mut redeemed = self.redeemed.lock().unwrap();
+        let expired = now().saturating_sub(ttl.as_secs());
+        *redeemed = redeemed.split_off(&(expired, 0));
+
+        redeemed.insert((issued, seed))
+    }
+
+    /// Time of issue of `seed`, if it was issued under this key.
+    fn issued(&self, seed: u128) -> Option<u64> {
+        let mut block = GenericArray::from(seed.to_le_bytes());
+        self.cipher.decrypt_block(&mut block);
+
+        let plain = u128::from_le_bytes(block.into());
+        if (plain >> 96) as u32 != TAG {
+            return None;
+        }
+
+        Some(((plain >> 64) as u32) as u64)
+    }
+}
+
+/// Whether a seed issued at `issued` is neither from the future nor older
+/// than `ttl`.
+fn fresh(issued: u64, ttl: Option<Duration>) -> bool {
+    let now = now();
+
+    if issued > now + CLOCK_SKEW {
+        return false;
+    }
+
+    ttl.map_or(true, |ttl| now.saturating_sub(issued) <= ttl.as_secs())
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
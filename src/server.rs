@@ -1,159 +1,990 @@
 //! Methods for preprocessing and responding to RAID-PIR queries.
 
 use std::collections::HashMap;
-use std::ops::BitXor;
-use std::ops::BitXorAssign;
-use std::sync::RwLock;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use bitvec::prelude::*;
 use rand::rngs::StdRng; // TODO: different PRNGs?
 use rand::{RngCore, SeedableRng};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::russians::{check_window, open_tables, write_tables, RussiansTables, DEFAULT_WINDOW};
+use crate::seeds::SeedIssuer;
+use crate::snapshot::Snapshot;
+use crate::storage::{Storage, View};
+use crate::store::{MemoryStore, SeedStore};
+use crate::types::{RaidPirBytes, RaidPirElement};
 use crate::util::*;
 
-const QUEUE_SIZE: usize = 32;
+/// Default number of preprocessed seeds kept in the queue.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 32;
+
+/// Default queue length below which the background worker refills it.
+pub const DEFAULT_LOW_WATER_MARK: usize = 8;
+
+/// Minimum time between two progress reports while building Four-Russians
+/// tables.
+const RUSSIANS_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 /**
  * RaidPir server.
  *
  * T is the type of database elements, and needs to be bit-xor-assignable
- * and have a default value, i.e. integer types.
+ * and have a default value, i.e. integer types. See [RaidPirElement].
  *
- * When not using integer values, care needs to be taken to ensure that all
- * values have the same size, and that T::default() returns an object of
- * that size. See [crate::types::RaidPirData].
+ * When not using integer values, all values need to have the same size.
+ * See [crate::types::RaidPirData].
  */
 #[derive(Debug)]
 pub struct RaidPirServer<T> {
-    db: Vec<T>,
-    russians: Option<Vec<Vec<T>>>,
-    servers: usize,
-    redundancy: usize,
+    data: RwLock<Data<T>>,
+    russians_window: usize,
+    params: RaidPirParams,
     queue: RwLock<HashMap<u128, T>>,
-    queue_used: RwLock<HashMap<u128, T>>,
+    queue_used: Box<dyn SeedStore<T>>,
+    seed_ttl: Option<Duration>,
+    max_handed_out: Option<usize>,
+    evicted: AtomicU64,
+    issuer: Option<SeedIssuer>,
+    capacity: usize,
+    low_water_mark: usize,
+    pool: ThreadPool,
+    worker: Arc<WorkerState>,
+}
+
+/// The database and the tables built from it, which change together when
+/// records are updated.
+#[derive(Debug)]
+struct Data<T> {
+    db: View<T>,
+    russians: Option<RussiansTables<T>>,
+    preprocess_russians: Option<RussiansTables<T>>,
+}
+
+/// State shared between a server and its background worker.
+#[derive(Debug, Default)]
+struct WorkerState {
+    flags: Mutex<WorkerFlags>,
+    wakeup: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct WorkerFlags {
+    running: bool,
+    stop: bool,
 }
 
-impl<T: Clone + Default + BitXor<Output=T> + BitXorAssign> RaidPirServer<T> {
+impl<T: RaidPirElement> RaidPirServer<T> {
     /**
      * Create a new server object and prepare the database.
+     *
+     * The database needs to contain exactly `params.blocks()` elements of
+     * size `params.element_size()`.
+     */
+    pub fn new(db: Vec<T>, id: usize, params: RaidPirParams, russians: bool) -> Result<Self> {
+        let size = params.element_size();
+        if let Some(x) = db.iter().find(|x| x.element_size() != size) {
+            return Err(RaidPirError::ElementSize { expected: size, actual: x.element_size() });
+        }
+
+        Self::with_storage(db, id, params, russians)
+    }
+
+    /**
+     * Create a new server object for a database kept in any [Storage], e.g.
+     * a [crate::storage::RaidPirDatabase].
+     *
+     * The database needs to contain exactly `params.blocks()` elements of
+     * size `params.element_size()`. It is neither copied nor rotated, padding
+     * and the server's offset are applied when accessing it.
+     */
+    pub fn with_storage<S>(storage: S, id: usize, params: RaidPirParams, russians: bool) -> Result<Self>
+    where
+        S: Storage<T> + 'static,
+    {
+        Self::with_shared(Arc::new(storage), id, params, russians)
+    }
+
+    /**
+     * Create a new server object for a database shared with other servers in
+     * the same process, e.g. to run all servers of a test deployment.
+     *
+     * Like [RaidPirServer::with_storage], but the database is only referenced,
+     * so any number of server ids can be served from a single copy:
+     *
+     * ```
+     * use std::sync::Arc;
+     *
+     * use raidpir::params::RaidPirParams;
+     * use raidpir::server::RaidPirServer;
+     *
+     * let db = Arc::new((0..100).collect::<Vec<u32>>());
+     * let params = RaidPirParams::new(db.len(), 4, 2, 4).unwrap();
+     *
+     * let servers: Vec<RaidPirServer<u32>> = (0..4)
+     *     .map(|i| RaidPirServer::with_shared(db.clone(), i, params, false).unwrap())
+     *     .collect();
+     * ```
      */
-    pub fn new(mut db: Vec<T>, id: usize, servers: usize, redundancy: usize, russians: bool) -> Self {
-        // TODO: move to param type?, store unpadded size
+    pub fn with_shared<S>(storage: Arc<S>, id: usize, params: RaidPirParams, russians: bool) -> Result<Self>
+    where
+        S: Storage<T> + 'static,
+    {
+        if storage.len() != params.blocks() {
+            return Err(RaidPirError::InvalidParams(format!(
+                "database has {} blocks, expected {}",
+                storage.len(), params.blocks()
+            )));
+        }
 
-        // pad databse to next multiple of (servers * 8)
-        if db.len() % (servers * 8) != 0 {
-            db.resize_with(
-                db.len() + (servers * 8) - (db.len() % (servers * 8)),
-                Default::default,
-            )
+        Self::with_view(storage, false, id, params, russians)
+    }
+
+    /**
+     * Create a new server object that only stores the records it needs.
+     *
+     * Server `id` only ever accesses its own chunk and the `redundancy - 1`
+     * chunks after it, so it doesn't need the rest of the database. `window`
+     * needs to contain exactly the records in [RaidPirParams::window], in
+     * that order, e.g. as extracted by
+     * [crate::storage::RaidPirDatabase::from_window]:
+     *
+     * ```
+     * use raidpir::params::RaidPirParams;
+     * use raidpir::server::RaidPirServer;
+     *
+     * let db: Vec<u32> = (0..100).collect();
+     * let params = RaidPirParams::new(db.len(), 4, 2, 4).unwrap();
+     *
+     * let window: Vec<u32> = params.window(3).into_iter().flat_map(|x| db[x].to_vec()).collect();
+     * let server = RaidPirServer::with_window(window, 3, params, false).unwrap();
+     * ```
+     */
+    pub fn with_window<S>(window: S, id: usize, params: RaidPirParams, russians: bool) -> Result<Self>
+    where
+        S: Storage<T> + 'static,
+    {
+        let expected: usize = params.window(id).iter().map(|x| x.len()).sum();
+        if window.len() != expected {
+            return Err(RaidPirError::InvalidParams(format!(
+                "window of server {} has {} blocks, expected {}",
+                id, window.len(), expected
+            )));
         }
 
-        assert!(db.len() % (servers * 8) == 0);
-        assert!(redundancy >= 2 && redundancy <= servers);
+        Self::with_view(Arc::new(window), true, id, params, russians)
+    }
 
-        let blocks_per_server = db.len() / servers;
-        db.rotate_left(id * blocks_per_server);
+    fn with_view(
+        storage: Arc<dyn Storage<T>>,
+        windowed: bool,
+        id: usize,
+        params: RaidPirParams,
+        russians: bool,
+    ) -> Result<Self> {
+        if id >= params.servers() {
+            return Err(RaidPirError::InvalidParams(format!(
+                "server id {} not in 0..{}",
+                id, params.servers()
+            )));
+        }
 
-        let russians = russians.then(|| db[0..blocks_per_server].chunks(8).map(|chunk| {
-            (0..=255).map(|i| {
-                BitVec::<Lsb0,u8>::from_vec(vec![i])
-                    .iter()
-                    .zip(chunk)
-                    .filter(|(q, _)| **q)
-                    .fold(T::default(), |a, (_, b)| a ^ b.clone())
-            }).collect()
-        }).collect());
+        // A window may consist only of padding.
+        let size = params.element_size();
+        if !storage.is_empty() && storage.element_size() != size {
+            return Err(RaidPirError::ElementSize { expected: size, actual: storage.element_size() });
+        }
 
-        Self {
-            db,
-            russians,
-            servers,
-            redundancy,
-            queue: RwLock::new(HashMap::with_capacity(QUEUE_SIZE)),
-            queue_used: RwLock::new(HashMap::new()),
+        // Elements of a fixed size (i.e. integers) can't be decoded from
+        // records of a different size.
+        let fixed = T::default().element_size();
+        if fixed != 0 && fixed != size {
+            return Err(RaidPirError::ElementSize { expected: size, actual: fixed });
         }
+
+        let blocks_per_server = params.blocks_per_server();
+        let db = View::new(storage, id, &params, windowed);
+
+        let pool = preprocess_pool(0)?;
+        let russians = match russians {
+            true => Some(build_russians(&pool, &db, 0..blocks_per_server, DEFAULT_WINDOW)?),
+            false => None,
+        };
+
+        Ok(Self {
+            data: RwLock::new(Data {
+                db,
+                russians,
+                preprocess_russians: None,
+            }),
+            russians_window: DEFAULT_WINDOW,
+            params,
+            queue: RwLock::new(HashMap::with_capacity(DEFAULT_QUEUE_CAPACITY)),
+            queue_used: Box::new(MemoryStore::new()),
+            seed_ttl: None,
+            max_handed_out: None,
+            evicted: AtomicU64::new(0),
+            issuer: None,
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            low_water_mark: DEFAULT_LOW_WATER_MARK,
+            pool,
+            worker: Arc::default(),
+        })
     }
 
     /**
-     * Preprocess queries by preparing a queue of seeds and partial answers.
+     * Parameters this server was built from.
+     */
+    pub fn params(&self) -> &RaidPirParams {
+        &self.params
+    }
+
+    /**
+     * Set the width of the Four-Russians windows in bits, rebuilding any
+     * existing tables. The default is [DEFAULT_WINDOW].
+     *
+     * Wider windows make responses (and preprocessing, see
+     * [RaidPirServer::set_preprocess_russians]) faster, at the cost of
+     * memory, see [crate::russians::memory_estimate]. To avoid building
+     * tables twice, create the server without tables and enable them
+     * afterwards with [RaidPirServer::set_russians].
+     */
+    pub fn set_russians_window(&mut self, window: usize) -> Result<()> {
+        let blocks_per_server = self.params.blocks_per_server();
+        let redundancy_blocks = self.params.redundancy() * blocks_per_server;
+        check_window(window)?;
+
+        // Drop old tables first, so they don't have to fit in memory at the
+        // same time as the new ones.
+        let data = self.data.get_mut().unwrap();
+        if data.russians.is_some() {
+            data.russians = None;
+            data.russians = Some(build_russians(&self.pool, &data.db, 0..blocks_per_server, window)?);
+        }
+        if data.preprocess_russians.is_some() {
+            data.preprocess_russians = None;
+            data.preprocess_russians =
+                Some(build_russians(&self.pool, &data.db, blocks_per_server..redundancy_blocks, window)?);
+        }
+
+        self.russians_window = window;
+
+        Ok(())
+    }
+
+    /**
+     * Enable or disable Four-Russians tables for the server's own chunk,
+     * which speed up responses.
+     */
+    pub fn set_russians(&mut self, enabled: bool) -> Result<()> {
+        let data = self.data.get_mut().unwrap();
+        data.russians = match enabled {
+            true if data.russians.is_some() => return Ok(()),
+            true => Some(build_russians(
+                &self.pool,
+                &data.db,
+                0..self.params.blocks_per_server(),
+                self.russians_window,
+            )?),
+            false => None,
+        };
+
+        Ok(())
+    }
+
+    /**
+     * Also use Four-Russians tables for preprocessing, covering the
+     * `redundancy - 1` chunks following the server's own. This makes
+     * preprocessing much faster, but multiplies the memory needed for the
+     * tables by `redundancy`, see [crate::russians::memory_estimate].
+     */
+    pub fn set_preprocess_russians(&mut self, enabled: bool) -> Result<()> {
+        let blocks_per_server = self.params.blocks_per_server();
+        let redundancy_blocks = self.params.redundancy() * blocks_per_server;
+
+        let data = self.data.get_mut().unwrap();
+        data.preprocess_russians = match enabled {
+            true if data.preprocess_russians.is_some() => return Ok(()),
+            true => Some(build_russians(
+                &self.pool,
+                &data.db,
+                blocks_per_server..redundancy_blocks,
+                self.russians_window,
+            )?),
+            false => None,
+        };
+
+        Ok(())
+    }
+
+    /**
+     * Set the number of threads used for preprocessing. 0 uses one thread
+     * per CPU, which is the default.
+     */
+    pub fn set_parallelism(&mut self, threads: usize) -> Result<()> {
+        self.pool = preprocess_pool(threads)?;
+        Ok(())
+    }
+
+    /**
+     * Set how many preprocessed seeds the queue holds, and the length below
+     * which the background worker refills it (see
+     * [RaidPirServer::start_worker]).
+     */
+    pub fn set_queue_size(&mut self, capacity: usize, low_water_mark: usize) -> Result<()> {
+        if capacity == 0 || low_water_mark > capacity {
+            return Err(RaidPirError::InvalidParams(format!(
+                "queue capacity {} with low-water mark {}",
+                capacity, low_water_mark
+            )));
+        }
+
+        self.capacity = capacity;
+        self.low_water_mark = low_water_mark;
+
+        Ok(())
+    }
+
+    /**
+     * Set how long handed out seeds are kept waiting for their query, and
+     * how many of them are kept at most. Older seeds are evicted first.
+     * `None` disables the respective limit, which is the default.
+     *
+     * Fails if `ttl` is `None` while stateless seeds are issued, see
+     * [RaidPirServer::set_seed_key].
+     */
+    pub fn set_seed_expiry(&mut self, ttl: Option<Duration>, max_handed_out: Option<usize>) -> Result<()> {
+        if ttl.is_none() && self.issuer.is_some() {
+            return Err(RaidPirError::InvalidParams("stateless seeds need a seed TTL".to_string()));
+        }
+
+        self.seed_ttl = ttl;
+        self.max_handed_out = max_handed_out;
+
+        Ok(())
+    }
+
+    /**
+     * Issue stateless seeds under the given key, see [crate::seeds].
+     *
+     * Queries for seeds that are not in the queue of handed out seeds (e.g.
+     * because they were issued before a restart, by another server sharing
+     * the key, or were evicted) are then still answered, by calculating the
+     * preprocessed part of the answer on demand. Seeds older than the seed
+     * TTL are rejected regardless, as are seeds this server already answered.
+     * Servers sharing the key only know about each other's answers if they
+     * share a seed store as well (see [RaidPirServer::set_seed_store]), so
+     * without one, a seed can be answered once by every server.
+     *
+     * Fails if no seed TTL is set, see [RaidPirServer::set_seed_expiry], as
+     * answered seeds have to be remembered until they expire.
+     */
+    pub fn set_seed_key(&mut self, key: [u8; 16]) -> Result<()> {
+        if self.seed_ttl.is_none() {
+            return Err(RaidPirError::InvalidParams("stateless seeds need a seed TTL".to_string()));
+        }
+
+        self.issuer = Some(SeedIssuer::new(key));
+
+        // Seeds in the queue were not issued under the key.
+        self.queue.write().unwrap().clear();
+
+        Ok(())
+    }
+
+    /**
+     * Whether `seed` is a stateless seed issued under this server's key and
+     * not older than the seed TTL. Always false without a key.
+     */
+    pub fn verify_seed(&self, seed: u128) -> bool {
+        match (self.issuer.as_ref(), self.seed_ttl) {
+            (Some(issuer), Some(ttl)) => issuer.verify(seed, Some(ttl)),
+            _ => false,
+        }
+    }
+
+    /**
+     * Keep handed out seeds in the given store instead of the default
+     * [MemoryStore]. Several servers with the same id and seed key can share
+     * a store (see [crate::store::FileStore]), so each of them can answer
+     * queries for seeds handed out by any other, and each seed is answered
+     * by only one of them.
+     */
+    pub fn set_seed_store<S: SeedStore<T> + 'static>(&mut self, store: S) {
+        self.queue_used = Box::new(store);
+    }
+
+    /**
+     * Number of seeds handed out and still waiting for their query.
+     */
+    pub fn handed_out(&self) -> Result<usize> {
+        self.queue_used.len()
+    }
+
+    /**
+     * Whether a query for `seed` would currently be answered, i.e. it was
+     * handed out and neither answered nor evicted yet, or it is a valid
+     * stateless seed (see [RaidPirServer::verify_seed]).
+     */
+    pub fn is_outstanding(&self, seed: u128) -> Result<bool> {
+        Ok(self.queue_used.contains(seed)? || self.verify_seed(seed))
+    }
+
+    /**
+     * Total number of handed out seeds evicted before their query arrived.
+     */
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Evict expired seeds now, rather than on the next call to
+     * [RaidPirServer::seed] or [RaidPirServer::response].
+     */
+    pub fn evict_expired(&self) -> Result<()> {
+        let evicted = self.queue_used.evict(self.seed_ttl, self.max_handed_out)?;
+
+        if evicted > 0 {
+            log::debug!("Evicted {} unused seeds", evicted);
+            self.evicted.fetch_add(evicted as u64, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Number of preprocessed seeds currently in the queue.
+     */
+    pub fn queued(&self) -> usize {
+        self.queue.read().unwrap().len()
+    }
+
+    /**
+     * Preprocess queries by filling the queue with seeds and partial answers.
+     *
+     * Entries are computed in parallel, see [RaidPirServer::set_parallelism].
      */
     pub fn preprocess(&self) {
-        let blocks_per_server = self.db.len() / self.servers;
+        let missing = self.capacity.saturating_sub(self.queued());
+
+        // Keep the database locked until the entries are queued, so that
+        // updates patch all of them.
+        let data = self.data.read().unwrap();
+        let entries: Vec<(u128, T)> = self.pool.install(|| {
+            (0..missing)
+                .into_par_iter()
+                .map_init(StdRng::from_entropy, |rng, _| self.preprocess_one(&data, rng))
+                .collect()
+        });
+
+        // Someone else might have refilled the queue in the meantime.
+        let mut queue = self.queue.write().unwrap();
+        let missing = self.capacity.saturating_sub(queue.len());
+        queue.extend(entries.into_iter().take(missing));
+    }
+
+    /// Draw a fresh seed and calculate its partial answer.
+    fn preprocess_one(&self, data: &Data<T>, rng: &mut StdRng) -> (u128, T) {
+        let seed = match self.issuer.as_ref() {
+            Some(issuer) => issuer.issue(),
+            None => ((rng.next_u64() as u128) << 64) | (rng.next_u64() as u128),
+        };
+
+        (seed, self.partial_answer(data, seed))
+    }
+
+    /// Calculate the part of the answer covering the redundancy chunks,
+    /// which only depends on the seed.
+    fn partial_answer(&self, data: &Data<T>, seed: u128) -> T {
+        let blocks_per_server = self.params.blocks_per_server();
+        let blocks = blocks_per_server * (self.params.redundancy() - 1);
+        let random_bits = rand_bitvec(seed, blocks);
+
+        // Starting from a zero element of the right size, so that answers
+        // have that size even if only padding was selected.
+        let mut answer = T::zero(self.params.element_size());
+        match data.preprocess_russians.as_ref() {
+            Some(russians) => russians.xor_into(&mut answer, random_bits.as_raw_slice()),
+            None => data.db.xor_selected(&mut answer, blocks_per_server, &random_bits),
+        }
 
-        let mut rng = StdRng::from_entropy();
+        answer
+    }
 
+    /**
+     * Return a seed from the queue.
+     *
+     * If the queue is empty, it is refilled first. With a background worker
+     * running, this only happens if the worker can't keep up. Fails if the
+     * seed can't be stored, see [RaidPirServer::set_seed_store].
+     */
+    pub fn seed(&self) -> Result<u128> {
         loop {
-            let seed = ((rng.next_u64() as u128) << 64) | (rng.next_u64() as u128);
-            let random_bits = rand_bitvec(seed, blocks_per_server * (self.redundancy - 1));
+            // Answers are moved to the handed out seeds with the database
+            // locked, so that updates patch each of them exactly once.
+            let data = self.data.read().unwrap();
+            let popped = {
+                let mut queue = self.queue.write().unwrap();
+                let seed = queue.keys().next().copied();
+                seed.map(|seed| (seed, queue.remove(&seed).unwrap(), queue.len()))
+            };
 
-            let mut db_iter = self.db.iter();
-            // TODO: replace with advance_by once that is stable
-            for _i in 0..blocks_per_server {
-                db_iter.next().unwrap();
-            }
+            let (seed, answer, remaining) = match popped {
+                Some(popped) => popped,
+                None => {
+                    drop(data);
+                    log::debug!("Queue empty, preprocessing synchronously");
+                    self.preprocess();
+                    continue;
+                }
+            };
 
-            let preprocessed = random_bits
-                .iter()
-                .zip(db_iter)
-                .filter(|(q, _)| **q)
-                .fold(T::default(), |a, (_, b)| a ^ b.clone());
+            self.queue_used.insert(seed, answer)?;
+            drop(data);
+            self.evict_expired()?;
 
-            let mut queue = self.queue.write().unwrap();
-            queue.insert(seed, preprocessed);
-            if queue.len() >= QUEUE_SIZE {
-                break;
+            if remaining < self.low_water_mark {
+                // Taking the lock makes sure the worker is either waiting or
+                // going to see the new queue length.
+                let _flags = self.worker.flags.lock().unwrap();
+                self.worker.wakeup.notify_all();
             }
+
+            return Ok(seed);
         }
     }
 
     /**
-     * Return a seed from the queue.
+     * Calculate response to the given query with the given seed.
+     *
+     * Each seed can only be used for a single response.
      */
-    pub fn seed(&self) -> u128 {
-        let len = {
-            let queue = self.queue.read().unwrap();
-            queue.len()
+    pub fn response(&self, seed: u128, query: &BitVec<Lsb0, u8>) -> Result<T> {
+        let blocks_per_server = self.params.blocks_per_server();
+        if query.len() != blocks_per_server {
+            return Err(RaidPirError::QueryLength { expected: blocks_per_server, actual: query.len() });
+        }
+
+        self.evict_expired()?;
+
+        let data = self.data.read().unwrap();
+        let stored = self.queue_used.remove(seed)?;
+
+        // Stateless seeds are recognized even after leaving the store, so
+        // they are redeemed to keep them from being answered again, here or
+        // by any other server sharing the store.
+        let redeemed = match (self.issuer.as_ref(), self.seed_ttl) {
+            (Some(issuer), Some(ttl)) => issuer.redeem(seed, ttl) && self.queue_used.redeem(seed)?,
+            _ => false,
         };
 
-        if len == 0 {
-            log::debug!("Refreshing queue!!");
-            self.preprocess();
+        let mut answer = match stored {
+            // Answered on demand by another server sharing the store.
+            Some(_) if self.issuer.is_some() && !redeemed => return Err(RaidPirError::UnknownSeed(seed)),
+            Some(answer) => answer,
+            // Issued by us (or a server sharing our key), but not
+            // preprocessed here, so do the expensive part now.
+            None if redeemed => self.partial_answer(&data, seed),
+            None => return Err(RaidPirError::UnknownSeed(seed)),
+        };
+
+        if let Some(russians) = data.russians.as_ref() {
+            russians.xor_into(&mut answer, query.as_raw_slice());
+        } else {
+            data.db.xor_selected(&mut answer, 0, query);
         }
 
-        let mut queue = self.queue.write().unwrap();
-        let mut queue_used = self.queue_used.write().unwrap();
+        Ok(answer)
+    }
+
+    /**
+     * Replace the record at `index` in the unpadded database, see
+     * [RaidPirServer::update_batch].
+     */
+    pub fn update(&self, index: usize, value: T) -> Result<()> {
+        self.update_batch(vec![(index, value)])
+    }
 
-        let seed = *queue.keys().next().unwrap();
+    /**
+     * Replace records while the server keeps running, given as pairs of
+     * index in the unpadded database and new value.
+     *
+     * Nothing is rebuilt. Instead, the change of each record is XORed into
+     * the Four-Russians table entries and into the preprocessed answers of
+     * all queued and handed out seeds that include it, so queries for seeds
+     * handed out before the update are answered from the new records.
+     * Records outside this server's window (see [RaidPirParams::window]) are
+     * ignored.
+     *
+     * Fails before changing anything if an index is out of range, a value
+     * has the wrong size, the database is read-only or shared with other
+     * servers (see [RaidPirServer::with_shared]), the tables are
+     * memory-mapped, or handed out seeds are kept in a store that can't be
+     * patched, e.g. a [crate::store::FileStore] shared with other servers.
+     */
+    pub fn update_batch<I>(&self, updates: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (usize, T)>,
+    {
+        let updates: Vec<(usize, T)> = updates.into_iter().collect();
+
+        let size = self.params.element_size();
+        for (index, value) in updates.iter() {
+            if *index >= self.params.blocks() {
+                return Err(RaidPirError::IndexOutOfRange { index: *index, blocks: self.params.blocks() });
+            }
+            if value.element_size() != size {
+                return Err(RaidPirError::ElementSize { expected: size, actual: value.element_size() });
+            }
+        }
 
-        queue_used.insert(seed, queue.remove(&seed).unwrap());
+        let mut guard = self.data.write().unwrap();
+        let data = &mut *guard;
 
-        seed
+        if data.russians.iter().chain(data.preprocess_russians.iter()).any(|t| t.is_mapped()) {
+            return Err(RaidPirError::ReadOnly("Four-Russians tables are memory-mapped".to_string()));
+        }
+        data.db.check_writable()?;
+        if !self.queue_used.is_patchable() {
+            return Err(RaidPirError::ReadOnly("handed out seeds are shared with other servers".to_string()));
+        }
+
+        // Change of each record, by position in the server's window.
+        let mut deltas = Vec::with_capacity(updates.len());
+        for (index, value) in updates {
+            if let Some(position) = data.db.position(index) {
+                let mut delta = data.db.get(position);
+                delta ^= &value;
+                data.db.set(position, &value)?;
+                deltas.push((position, delta));
+            }
+        }
+
+        let updated = deltas.len();
+        let blocks_per_server = self.params.blocks_per_server();
+        for (position, delta) in deltas.iter() {
+            let tables = match *position < blocks_per_server {
+                true => data.russians.as_mut().map(|t| (t, *position)),
+                false => data.preprocess_russians.as_mut().map(|t| (t, position - blocks_per_server)),
+            };
+
+            if let Some((tables, index)) = tables {
+                tables.update(index, delta)?;
+            }
+        }
+
+        // Only the chunks after the server's own are preprocessed.
+        let deltas: Vec<(usize, T)> = deltas
+            .into_iter()
+            .filter(|(position, _)| *position >= blocks_per_server)
+            .map(|(position, delta)| (position - blocks_per_server, delta))
+            .collect();
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        let blocks = blocks_per_server * (self.params.redundancy() - 1);
+        let mut patch = |seed: u128, answer: &mut T| {
+            let random_bits = rand_bitvec(seed, blocks);
+            let mut changed = false;
+            for (_, delta) in deltas.iter().filter(|(i, _)| random_bits[*i]) {
+                *answer ^= delta;
+                changed = true;
+            }
+            changed
+        };
+
+        for (seed, answer) in self.queue.write().unwrap().iter_mut() {
+            patch(*seed, answer);
+        }
+        let patched = self.queue_used.patch(&mut patch)?;
+        log::debug!("Updated {} records, patched {} handed out seeds", updated, patched);
+
+        Ok(())
     }
+}
 
+impl<T: RaidPirBytes> RaidPirServer<T> {
     /**
-     * Calculate response to the given query with the given seed.
+     * Stable digest of the records this server accesses, i.e. its window
+     * after padding and rotating by the server id, and of the parameters.
+     * Servers with the same id have the same digest whether they store the
+     * whole database or only their window.
+     *
+     * Like [RaidPirParams::fingerprint], this is an FNV-1a hash. It detects
+     * changed data, not deliberate tampering.
+     */
+    pub fn digest(&self) -> u64 {
+        self.data.read().unwrap().digest(&self.params)
+    }
+
+    /**
+     * Save this server's Four-Russians tables to a file, so they can be
+     * memory-mapped on the next start instead of being built again, see
+     * [RaidPirServer::load_russians].
      */
-    pub fn response(&self, seed: u128, query: &BitVec<Lsb0, u8>) -> T {
-        let mut answer = {
-            let mut queue_used = self.queue_used.write().unwrap();
-            queue_used.remove(&seed).unwrap()
+    pub fn save_russians<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let data = self.data.read().unwrap();
+        write_tables(
+            path,
+            data.digest(&self.params),
+            self.params.element_size(),
+            data.russians.as_ref(),
+            data.preprocess_russians.as_ref(),
+        )
+    }
+
+    /**
+     * Memory-map Four-Russians tables saved with
+     * [RaidPirServer::save_russians], replacing any tables built before. The
+     * window width is taken from the file.
+     *
+     * Fails if the tables were saved for a different database, parameters or
+     * server id. To avoid building tables first, create the server without
+     * them.
+     */
+    pub fn load_russians<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let data = self.data.get_mut().unwrap();
+        let (russians, preprocess_russians) =
+            open_tables(path, data.digest(&self.params), self.params.element_size())?;
+
+        // The digest already covers the parameters, so this only fails for
+        // files not written by save_russians.
+        let blocks_per_server = self.params.blocks_per_server();
+        let preprocess_blocks = (self.params.redundancy() - 1) * blocks_per_server;
+        if russians.as_ref().is_some_and(|t| t.len() != blocks_per_server)
+            || preprocess_russians.as_ref().is_some_and(|t| t.len() != preprocess_blocks)
+        {
+            return Err(RaidPirError::InvalidDatabase(
+                "tables do not cover this server's chunks".to_string(),
+            ));
+        }
+
+        if let Some(window) = russians.as_ref().or(preprocess_russians.as_ref()).map(|t| t.window()) {
+            self.russians_window = window;
+        }
+        data.russians = russians;
+        data.preprocess_russians = preprocess_russians;
+
+        Ok(())
+    }
+
+    /**
+     * Save all preprocessed seeds, queued and handed out, to a snapshot file.
+     * See [RaidPirServer::load_snapshot].
+     *
+     * Seeds kept in a store that persists them by itself (see
+     * [SeedStore::snapshot]) are not included.
+     */
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let element_size = self.params.element_size();
+        let to_bytes = |(seed, x): (u128, &T)| {
+            let mut bytes = Vec::with_capacity(element_size);
+            x.write_bytes(&mut bytes);
+            // Answers that nothing was XORed into may be empty.
+            bytes.resize(element_size, 0);
+            (seed, bytes)
         };
 
-        if let Some(russians) = self.russians.as_ref() {
-            query
-                .as_raw_slice()
-                .iter()
-                .enumerate()
-                .map(|(i, q)| russians[i][*q as usize].clone())
-                .for_each(|x| answer ^= x);
-        } else {
-            query
-                .iter()
-                .zip(self.db.iter())
-                .filter(|(q, _)| **q)
-                .for_each(|(_, x)| answer ^= x.clone());
+        // Answers and digest need to match, so no updates in between.
+        let data = self.data.read().unwrap();
+        let queued = self.queue.read().unwrap().iter().map(|(seed, x)| to_bytes((*seed, x))).collect();
+        let handed_out = self.queue_used.snapshot()?.iter().map(|(seed, x)| to_bytes((*seed, x))).collect();
+
+        Snapshot {
+            digest: data.digest(&self.params),
+            element_size,
+            queued,
+            handed_out,
         }
+        .write(path)
+    }
 
-        answer
+    /**
+     * Restore preprocessed seeds from a snapshot file, so that they don't
+     * have to be preprocessed again, and seeds handed out before a restart
+     * can still be queried. Returns the number of restored seeds.
+     *
+     * Fails if the snapshot was taken from a different database, parameters
+     * or server id. Restored handed out seeds count as handed out now for
+     * their TTL. Load snapshots after [RaidPirServer::set_seed_key], which
+     * clears the queue.
+     */
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let snapshot = Snapshot::read(path)?;
+
+        let data = self.data.read().unwrap();
+        let digest = data.digest(&self.params);
+        if snapshot.digest != digest || snapshot.element_size != self.params.element_size() {
+            return Err(RaidPirError::InvalidSnapshot(format!(
+                "snapshot digest {:#018x} does not match database ({:#018x})",
+                snapshot.digest, digest
+            )));
+        }
+
+        let restored = snapshot.queued.len() + snapshot.handed_out.len();
+
+        self.queue
+            .write()
+            .unwrap()
+            .extend(snapshot.queued.into_iter().map(|(seed, x)| (seed, T::from_bytes(&x))));
+
+        for (seed, x) in snapshot.handed_out {
+            self.queue_used.insert(seed, T::from_bytes(&x))?;
+        }
+        drop(data);
+        self.evict_expired()?;
+
+        Ok(restored)
+    }
+}
+
+impl<T: RaidPirBytes> Data<T> {
+    /// See [RaidPirServer::digest].
+    fn digest(&self, params: &RaidPirParams) -> u64 {
+        let mut buffer = Vec::with_capacity(params.element_size());
+
+        (0..self.db.len()).fold(fnv1a(FNV_OFFSET, &params.fingerprint().to_le_bytes()), |hash, i| {
+            buffer.clear();
+            self.db.get(i).write_bytes(&mut buffer);
+            fnv1a(hash, &buffer)
+        })
+    }
+}
+
+/// Build Four-Russians tables on the preprocessing pool, logging progress
+/// every few seconds for large databases.
+fn build_russians<T: RaidPirElement>(
+    pool: &ThreadPool,
+    db: &View<T>,
+    range: Range<usize>,
+    window: usize,
+) -> Result<RussiansTables<T>> {
+    let start = Instant::now();
+    let last_report = Mutex::new(start);
+
+    let get = |i: usize| db.get(range.start + i);
+    let tables = pool.install(|| {
+        RussiansTables::build(range.len(), window, get, |done, total| {
+            // Some other thread is reporting right now.
+            let mut last_report = match last_report.try_lock() {
+                Ok(last_report) => last_report,
+                Err(_) => return,
+            };
+
+            if last_report.elapsed() >= RUSSIANS_PROGRESS_INTERVAL {
+                *last_report = Instant::now();
+                log::info!(
+                    "Built {}/{} Four-Russians tables ({:.0}%)",
+                    done, total, 100.0 * done as f64 / total as f64
+                );
+            }
+        })
+    })?;
+
+    log::debug!("Built Four-Russians tables in {:.2}s", start.elapsed().as_secs_f64());
+
+    Ok(tables)
+}
+
+/// Preprocessing gets its own thread pool, so it can't be starved by callers
+/// blocking rayon's global pool, e.g. clients waiting for a server.
+fn preprocess_pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("raidpir-preprocess-{}", i))
+        .build()
+        .map_err(|e| RaidPirError::InvalidParams(e.to_string()))
+}
+
+impl<T: RaidPirElement + 'static> RaidPirServer<T> {
+    /**
+     * Start a background worker that refills the queue whenever it drops
+     * below the low-water mark, see [RaidPirServer::set_queue_size].
+     *
+     * The worker keeps the server alive until it is stopped, either with
+     * [PreprocessWorker::stop] or by dropping the handle. Only one worker can
+     * run per server.
+     */
+    pub fn start_worker(self: &Arc<Self>) -> Result<PreprocessWorker> {
+        {
+            let mut flags = self.worker.flags.lock().unwrap();
+            if flags.running {
+                return Err(RaidPirError::InvalidParams("worker already running".to_string()));
+            }
+            flags.running = true;
+            flags.stop = false;
+        }
+
+        let server = self.clone();
+        let handle = thread::Builder::new()
+            .name("raidpir-worker".to_string())
+            .spawn(move || server.run_worker())?;
+
+        Ok(PreprocessWorker {
+            state: self.worker.clone(),
+            handle: Some(handle),
+        })
+    }
+
+    fn run_worker(&self) {
+        loop {
+            {
+                let mut flags = self.worker.flags.lock().unwrap();
+                loop {
+                    if flags.stop {
+                        flags.running = false;
+                        return;
+                    }
+
+                    if self.queued() < self.low_water_mark {
+                        break;
+                    }
+
+                    flags = self.worker.wakeup.wait(flags).unwrap();
+                }
+            }
+
+            log::debug!("Refilling queue");
+            self.preprocess();
+        }
+    }
+}
+
+/**
+ * Handle to a server's background worker, see
+ * [RaidPirServer::start_worker]. Stops the worker when dropped.
+ */
+#[derive(Debug)]
+pub struct PreprocessWorker {
+    state: Arc<WorkerState>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PreprocessWorker {
+    /**
+     * Stop the worker, waiting for a refill in progress to finish.
+     */
+    pub fn stop(self) {
+        // Dropping does the actual work.
+    }
+}
+
+impl Drop for PreprocessWorker {
+    fn drop(&mut self) {
+        self.state.flags.lock().unwrap().stop = true;
+        self.state.wakeup.notify_all();
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
     }
 }
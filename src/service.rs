@@ -0,0 +1,491 @@
+//! Running RAID-PIR lookups over a [Transport], for both sides of the
+//! exchange.
+
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use bitvec::prelude::*;
+use rayon::prelude::*;
+
+use crate::client::{PreparedQuery, RaidPirClient};
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::protocol::Message;
+use crate::server::RaidPirServer;
+use crate::transport::Transport;
+use crate::types::{RaidPirBytes, RaidPirData, RaidPirElement};
+
+/// Maximum number of seeds granted to a single connection that can still be
+/// queried, i.e. were neither used nor evicted by the server.
+pub const MAX_OUTSTANDING_SEEDS: usize = 1024;
+
+/// Default number of queries a client keeps in flight per connection.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 8;
+
+/**
+ * Serve lookups on the given connection until the client closes it.
+ *
+ * Sends the server's parameters and a fresh seed. Afterwards, the client can
+ * send queries for any seed granted on this connection, and request more
+ * seeds with [Message::SeedRequest]. Queries are answered in order, so
+ * clients can pipeline them. Errors are reported to the client before being
+ * returned, and end the connection.
+ */
+pub fn serve_connection<T, C>(server: &RaidPirServer<T>, transport: &mut C) -> Result<()>
+where
+    T: RaidPirBytes,
+    C: Transport + ?Sized,
+{
+    transport.send(&Message::Hello(*server.params()))?;
+
+    let mut granted = HashSet::new();
+    grant_seeds(server, transport, &mut granted, 1)?;
+
+    loop {
+        let msg = match transport.recv() {
+            Ok(msg) => msg,
+            // Client closed the connection between two messages.
+            Err(RaidPirError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let result = match msg {
+            // Only accept seeds granted on this connection, so clients can't
+            // use up seeds handed out to someone else. Stateless seeds can be
+            // used on any connection, they are answered on demand if needed,
+            // but only once, see RaidPirServer::set_seed_key.
+            Message::Query { seed, query } if granted.remove(&seed) || server.verify_seed(seed) => server
+                .response(seed, &query)
+                .and_then(|answer| transport.send(&Message::Response(answer.to_bytes()))),
+            Message::Query { seed, .. } => Err(RaidPirError::UnknownSeed(seed)),
+            Message::SeedRequest(count) => grant_seeds(server, transport, &mut granted, count as usize),
+            Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+            msg => Err(unexpected("Query or SeedRequest", msg)),
+        };
+
+        if let Err(e) = result {
+            // The client may already be gone, the original error is more
+            // interesting than the failed write.
+            let _ = transport.send(&Message::Error(e.to_string()));
+            return Err(e);
+        }
+    }
+}
+
+fn grant_seeds<T, C>(server: &RaidPirServer<T>, transport: &mut C, granted: &mut HashSet<u128>, count: usize) -> Result<()>
+where
+    T: RaidPirElement,
+    C: Transport + ?Sized,
+{
+    if granted.len() + count > MAX_OUTSTANDING_SEEDS {
+        prune_granted(server, granted)?;
+    }
+
+    if granted.len() + count > MAX_OUTSTANDING_SEEDS {
+        return Err(RaidPirError::Protocol(format!(
+            "more than {} outstanding seeds requested",
+            MAX_OUTSTANDING_SEEDS
+        )));
+    }
+
+    for _ in 0..count {
+        let seed = server.seed()?;
+        granted.insert(seed);
+        transport.send(&Message::SeedGrant(seed))?;
+    }
+
+    Ok(())
+}
+
+/// Forget granted seeds that can no longer be queried, e.g. because the
+/// server evicted them, so that only outstanding seeds count against
+/// [MAX_OUTSTANDING_SEEDS].
+pub(crate) fn prune_granted<T: RaidPirElement>(server: &RaidPirServer<T>, granted: &mut HashSet<u128>) -> Result<()> {
+    server.evict_expired()?;
+
+    let mut outstanding = HashSet::with_capacity(granted.len());
+    for &seed in granted.iter() {
+        if server.is_outstanding(seed)? {
+            outstanding.insert(seed);
+        }
+    }
+    *granted = outstanding;
+
+    Ok(())
+}
+
+/**
+ * Receive parameters and a seed from each server, in parallel.
+ *
+ * Fails if the servers were not all built from the same parameters. Errors
+ * are attributed to the server (i.e. index of the transport) that caused
+ * them.
+ */
+pub fn fetch_seeds<C: Transport + Send>(transports: &mut [C]) -> Result<(RaidPirParams, Vec<u128>)> {
+    let hellos: Vec<(RaidPirParams, u128)> = transports
+        .par_iter_mut()
+        .enumerate()
+        .map(|(i, transport)| recv_seed(transport).map_err(|e| e.at_server(i)))
+        .with_max_len(1) // Ensure each transport gets a thread
+        .collect::<Result<_>>()?;
+
+    let params = hellos
+        .first()
+        .map(|(params, _)| *params)
+        .ok_or(RaidPirError::NoServers)?;
+
+    for (i, (p, _)) in hellos.iter().enumerate() {
+        params.check_fingerprint(p.fingerprint()).map_err(|e| e.at_server(i))?;
+    }
+
+    Ok((params, hellos.into_iter().map(|(_, seed)| seed).collect()))
+}
+
+/**
+ * Send each server its share of the query and collect the responses, in
+ * parallel.
+ *
+ * Errors are attributed to the server that caused them.
+ */
+pub fn fetch_responses<C: Transport + Send>(
+    transports: &mut [C],
+    seeds: &[u128],
+    queries: Vec<BitVec<Lsb0, u8>>,
+) -> Result<Vec<RaidPirData>> {
+    transports
+        .par_iter_mut()
+        .zip(seeds.par_iter().zip(queries.into_par_iter()))
+        .enumerate()
+        .map(|(i, (transport, (seed, query)))| {
+            transport
+                .send(&Message::Query { seed: *seed, query })
+                .and_then(|_| match transport.recv()? {
+                    Message::Response(data) => Ok(RaidPirData::new(data)),
+                    Message::Error(msg) => Err(RaidPirError::Remote(msg)),
+                    msg => Err(unexpected("Response", msg)),
+                })
+                .map_err(|e| e.at_server(i))
+        })
+        .with_max_len(1)
+        .collect()
+}
+
+fn recv_seed<C: Transport>(transport: &mut C) -> Result<(RaidPirParams, u128)> {
+    let params = match transport.recv()? {
+        Message::Hello(params) => params,
+        Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+        msg => return Err(unexpected("Hello", msg)),
+    };
+
+    match transport.recv()? {
+        Message::SeedGrant(seed) => Ok((params, seed)),
+        Message::Error(msg) => Err(RaidPirError::Remote(msg)),
+        msg => Err(unexpected("SeedGrant", msg)),
+    }
+}
+
+fn unexpected(expected: &str, msg: Message) -> RaidPirError {
+    RaidPirError::Protocol(format!("expected {}, got {}", expected, msg.name()))
+}
+
+/**
+ * Privately retrieve the element at `index`, using one freshly opened
+ * transport per server, in order of server id.
+ */
+pub fn lookup<C: Transport + Send>(transports: &mut [C], index: usize) -> Result<RaidPirData> {
+    let (params, seeds) = fetch_seeds(transports)?;
+
+    let client = RaidPirClient::new(params);
+    let queries = client.query(index, &seeds)?;
+
+    let responses = fetch_responses(transports, &seeds, queries)?;
+
+    client.combine(responses)
+}
+
+/**
+ * Long-lived client connection to a single server.
+ *
+ * Seeds granted by the server are kept until they are used, so any number
+ * of lookups can be run over the same connection.
+ */
+#[derive(Debug)]
+pub struct Connection<C> {
+    transport: C,
+    params: RaidPirParams,
+    seeds: VecDeque<u128>,
+    deadline: Option<Instant>,
+}
+
+impl<C: Transport> Connection<C> {
+    /**
+     * Wait for the server's parameters and first seed on a freshly opened
+     * transport.
+     */
+    pub fn open(mut transport: C) -> Result<Self> {
+        let (params, seed) = recv_seed(&mut transport)?;
+
+        Ok(Self {
+            transport,
+            params,
+            seeds: VecDeque::from(vec![seed]),
+            deadline: None,
+        })
+    }
+
+    /**
+     * Parameters the server was built from.
+     */
+    pub fn params(&self) -> &RaidPirParams {
+        &self.params
+    }
+
+    /**
+     * Number of seeds granted by the server and not yet used.
+     */
+    pub fn available_seeds(&self) -> usize {
+        self.seeds.len()
+    }
+
+    /**
+     * Fail all subsequent sends and receives once `deadline` has passed.
+     * `None` leaves the transport's timeout alone.
+     */
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /**
+     * Unwrap the underlying transport.
+     */
+    pub fn into_inner(self) -> C {
+        self.transport
+    }
+
+    /**
+     * Make sure at least `count` seeds are available, requesting more from
+     * the server if necessary.
+     */
+    pub fn reserve_seeds(&mut self, count: usize) -> Result<()> {
+        let missing = count.saturating_sub(self.seeds.len());
+        if missing == 0 {
+            return Ok(());
+        }
+
+        let request = u32::try_from(missing)
+            .map_err(|_| RaidPirError::InvalidParams(format!("cannot request {} seeds", missing)))?;
+        self.send(&Message::SeedRequest(request))?;
+
+        for _ in 0..missing {
+            match self.recv()? {
+                Message::SeedGrant(seed) => self.seeds.push_back(seed),
+                Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+                msg => return Err(unexpected("SeedGrant", msg)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Take `count` seeds for upcoming queries, requesting more from the
+     * server if necessary.
+     */
+    pub fn take_seeds(&mut self, count: usize) -> Result<Vec<u128>> {
+        self.reserve_seeds(count)?;
+        Ok(self.seeds.drain(..count).collect())
+    }
+
+    /**
+     * Send the given queries and collect the responses, in order.
+     *
+     * Up to `depth` queries are kept in flight, so the round trips overlap
+     * without either side's socket buffers filling up.
+     */
+    pub fn pipeline(&mut self, queries: Vec<(u128, BitVec<Lsb0, u8>)>, depth: usize) -> Result<Vec<RaidPirData>> {
+        let total = queries.len();
+        let depth = depth.max(1);
+
+        let mut queries = queries.into_iter();
+        let mut responses = Vec::with_capacity(total);
+        let mut sent = 0;
+
+        while responses.len() < total {
+            while sent < total && sent - responses.len() < depth {
+                let (seed, query) = queries.next().unwrap();
+                self.send(&Message::Query { seed, query })?;
+                sent += 1;
+            }
+
+            let response = match self.recv()? {
+                Message::Response(data) => RaidPirData::new(data),
+                Message::Error(msg) => return Err(RaidPirError::Remote(msg)),
+                msg => return Err(unexpected("Response", msg)),
+            };
+
+            if response.element_size() != self.params.element_size() {
+                return Err(RaidPirError::ElementSize {
+                    expected: self.params.element_size(),
+                    actual: response.element_size(),
+                });
+            }
+
+            responses.push(response);
+        }
+
+        Ok(responses)
+    }
+
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        self.apply_deadline()?;
+        self.transport.send(msg)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        self.apply_deadline()?;
+        self.transport.recv()
+    }
+
+    /// Set the transport's timeout to the time remaining until the deadline.
+    fn apply_deadline(&mut self) -> Result<()> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "deadline exceeded").into());
+        }
+
+        self.transport.set_timeout(Some(remaining))
+    }
+}
+
+/// Number of seeds requested from each server at once.
+pub const SEED_BATCH_SIZE: usize = MAX_OUTSTANDING_SEEDS / 4;
+
+/**
+ * Check that all connections are to servers built from the same parameters,
+ * and return those.
+ */
+pub fn check_params<C>(connections: &[Connection<C>]) -> Result<RaidPirParams> {
+    let params = connections
+        .first()
+        .map(|c| c.params)
+        .ok_or(RaidPirError::NoServers)?;
+
+    for (i, c) in connections.iter().enumerate() {
+        params.check_fingerprint(c.params.fingerprint()).map_err(|e| e.at_server(i))?;
+    }
+
+    Ok(params)
+}
+
+/**
+ * Take `count` seeds from every server, requesting them in batches, and
+ * expand them into prepared queries.
+ */
+pub fn fetch_prepared<C: Transport + Send>(connections: &mut [Connection<C>], count: usize) -> Result<Vec<PreparedQuery>> {
+    let client = RaidPirClient::new(check_params(connections)?);
+    let mut prepared = Vec::with_capacity(count);
+
+    while prepared.len() < count {
+        let batch = (count - prepared.len()).min(SEED_BATCH_SIZE);
+
+        let seeds: Vec<Vec<u128>> = connections
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, c)| c.take_seeds(batch).map_err(|e| e.at_server(i)))
+            .with_max_len(1) // Ensure each connection gets a thread
+            .collect::<Result<_>>()?;
+
+        let batch: Vec<PreparedQuery> = (0..batch)
+            .into_par_iter()
+            .map(|k| client.prepare(&seeds.iter().map(|s| s[k]).collect::<Vec<_>>()))
+            .collect::<Result<_>>()?;
+
+        prepared.extend(batch);
+    }
+
+    Ok(prepared)
+}
+
+/**
+ * Privately retrieve the elements at `indices` over open connections, using
+ * one prepared query per index.
+ *
+ * Up to `depth` queries are kept in flight per server. Errors are attributed
+ * to the server that caused them.
+ */
+pub fn lookup_prepared<C: Transport + Send>(
+    connections: &mut [Connection<C>],
+    indices: &[usize],
+    prepared: Vec<PreparedQuery>,
+    depth: usize,
+) -> Result<Vec<RaidPirData>> {
+    let client = RaidPirClient::new(check_params(connections)?);
+
+    if prepared.len() != indices.len() {
+        return Err(RaidPirError::InvalidParams(format!(
+            "{} prepared queries for {} indices",
+            prepared.len(), indices.len()
+        )));
+    }
+
+    let mut queries: Vec<Vec<(u128, BitVec<Lsb0, u8>)>> = vec![Vec::with_capacity(indices.len()); connections.len()];
+    for (&index, prepared) in indices.iter().zip(prepared) {
+        let seeds = prepared.seeds().to_vec();
+        let round = client.query_prepared(index, prepared)?;
+        for (i, (seed, query)) in seeds.into_iter().zip(round).enumerate() {
+            queries[i].push((seed, query));
+        }
+    }
+
+    let mut responses: Vec<std::vec::IntoIter<RaidPirData>> = connections
+        .par_iter_mut()
+        .zip(queries.into_par_iter())
+        .enumerate()
+        .map(|(i, (c, queries))| {
+            c.pipeline(queries, depth)
+                .map(|r| r.into_iter())
+                .map_err(|e| e.at_server(i))
+        })
+        .with_max_len(1)
+        .collect::<Result<_>>()?;
+
+    indices
+        .iter()
+        .map(|_| client.combine(responses.iter_mut().map(|r| r.next().unwrap()).collect()))
+        .collect()
+}
+
+/**
+ * Privately retrieve the elements at `indices` over open connections, one
+ * per server in order of server id.
+ *
+ * Seeds are requested in batches and up to `depth` queries are kept in
+ * flight per server. Errors are attributed to the server that caused them.
+ */
+pub fn lookup_many<C: Transport + Send>(
+    connections: &mut [Connection<C>],
+    indices: &[usize],
+    depth: usize,
+) -> Result<Vec<RaidPirData>> {
+    let params = check_params(connections)?;
+
+    // Check indices before using up any seeds.
+    if let Some(&index) = indices.iter().find(|&&index| index >= params.blocks()) {
+        return Err(RaidPirError::IndexOutOfRange { index, blocks: params.blocks() });
+    }
+
+    let mut results = Vec::with_capacity(indices.len());
+    for indices in indices.chunks(SEED_BATCH_SIZE) {
+        let prepared = fetch_prepared(connections, indices.len())?;
+        results.extend(lookup_prepared(connections, indices, prepared, depth)?);
+    }
+
+    Ok(results)
+}
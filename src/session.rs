@@ -0,0 +1,265 @@
+//! High-level client running complete lookups against a set of servers.
+
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::pool::SeedPool;
+use crate::service::{check_params, fetch_prepared, lookup_prepared, Connection, DEFAULT_PIPELINE_DEPTH, SEED_BATCH_SIZE};
+use crate::transport::Transport;
+use crate::types::RaidPirData;
+
+/// Default deadline for each server to complete a round.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default number of times a failed round is retried.
+pub const DEFAULT_RETRIES: usize = 2;
+
+/**
+ * Client session running complete lookups against a set of servers.
+ *
+ * A transport to every server is opened using the `connect` callback, which
+ * is given the server id and the timeout. Connections are kept open and
+ * reused for subsequent lookups. If any server fails, misbehaves or misses
+ * its deadline, all connections are dropped and the whole round is retried
+ * over fresh connections with fresh seeds. Once all retries are used up,
+ * the error of the last round is returned, naming the offending server.
+ *
+ * The parameters of the servers are pinned when connecting for the first
+ * time, so servers that change parameters later on are rejected.
+ *
+ * ```
+ * use std::sync::Arc;
+ * use std::thread;
+ *
+ * use raidpir::params::RaidPirParams;
+ * use raidpir::server::RaidPirServer;
+ * use raidpir::service::serve_connection;
+ * use raidpir::session::RaidPirSession;
+ * use raidpir::transport::ChannelTransport;
+ * use raidpir::types::RaidPirData;
+ *
+ * let db: Vec<RaidPirData> = (0..100).map(|i| RaidPirData::new(vec![i; 4])).collect();
+ * let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+ *
+ * let servers: Vec<Arc<RaidPirServer<RaidPirData>>> = (0..2)
+ *     .map(|i| Arc::new(RaidPirServer::new(db.clone(), i, params, true).unwrap()))
+ *     .collect();
+ *
+ * let mut session = RaidPirSession::new(2, |i, _timeout| {
+ *     let (client, mut transport) = ChannelTransport::pair();
+ *     let server = servers[i].clone();
+ *     thread::spawn(move || serve_connection(&server, &mut transport));
+ *     Ok(client)
+ * });
+ *
+ * assert_eq!(session.lookup(42).unwrap().as_slice(), &[42; 4]);
+ * ```
+ */
+#[derive(Debug)]
+pub struct RaidPirSession<C, F> {
+    connect: F,
+    servers: usize,
+    timeout: Duration,
+    retries: usize,
+    depth: usize,
+    params: Option<RaidPirParams>,
+    connections: Vec<Connection<C>>,
+    pool: SeedPool,
+}
+
+impl<C, F> RaidPirSession<C, F>
+where
+    C: Transport + Send,
+    F: Fn(usize, Duration) -> Result<C> + Sync,
+{
+    /**
+     * Create a new session for the given number of servers.
+     */
+    pub fn new(servers: usize, connect: F) -> Self {
+        Self {
+            connect,
+            servers,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            depth: DEFAULT_PIPELINE_DEPTH,
+            params: None,
+            connections: Vec::new(),
+            pool: SeedPool::default(),
+        }
+    }
+
+    /**
+     * Set the deadline for each server to complete a whole round, i.e.
+     * connecting, sending a seed and answering the query.
+     */
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /**
+     * Set how many times a failed round is retried.
+     */
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /**
+     * Set how many queries are kept in flight per server when looking up
+     * several elements at once.
+     */
+    pub fn set_pipeline_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /**
+     * Set time after which prefetched seeds are discarded.
+     */
+    pub fn set_seed_ttl(&mut self, ttl: Duration) {
+        self.pool.set_ttl(ttl);
+    }
+
+    /**
+     * Only accept servers built from the given parameters.
+     */
+    pub fn set_params(&mut self, params: RaidPirParams) {
+        self.params = Some(params);
+    }
+
+    /**
+     * Parameters of the servers, if known.
+     */
+    pub fn params(&self) -> Option<&RaidPirParams> {
+        self.params.as_ref()
+    }
+
+    /**
+     * Close all open connections. The next lookup opens new ones.
+     */
+    pub fn disconnect(&mut self) {
+        self.connections.clear();
+        self.pool.clear();
+    }
+
+    /**
+     * Fetch and expand seeds for the next `count` lookups ahead of time,
+     * so those only take a single round trip.
+     */
+    pub fn prefetch(&mut self, count: usize) -> Result<()> {
+        self.retry(|session| {
+            session.ensure_connected()?;
+            session.pool.fill(&mut session.connections, count)
+        })
+    }
+
+    /**
+     * Privately retrieve the element at `index`.
+     */
+    pub fn lookup(&mut self, index: usize) -> Result<RaidPirData> {
+        Ok(self.lookup_many(&[index])?.remove(0))
+    }
+
+    /**
+     * Privately retrieve the elements at `indices`, pipelining the queries.
+     *
+     * Prefetched seeds are used first, further seeds are fetched as needed.
+     */
+    pub fn lookup_many(&mut self, indices: &[usize]) -> Result<Vec<RaidPirData>> {
+        self.retry(|session| session.round(indices))
+    }
+
+    /// Run `f`, retrying over fresh connections if a server failed.
+    fn retry<R>(&mut self, mut f: impl FnMut(&mut Self) -> Result<R>) -> Result<R> {
+        let mut attempt = 0;
+
+        loop {
+            match f(self) {
+                Ok(result) => return Ok(result),
+                // Only failures of individual servers are worth retrying,
+                // errors on our side (e.g. an invalid index) are permanent.
+                Err(e @ RaidPirError::Server { .. }) if attempt < self.retries => {
+                    log::warn!("Round failed, retrying over fresh connections: {}", e);
+                    self.disconnect();
+                    attempt += 1;
+                }
+                Err(e @ RaidPirError::Server { .. }) => {
+                    self.disconnect();
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn round(&mut self, indices: &[usize]) -> Result<Vec<RaidPirData>> {
+        let params = self.ensure_connected()?;
+
+        // Check indices before using up any seeds.
+        if let Some(&index) = indices.iter().find(|&&index| index >= params.blocks()) {
+            return Err(RaidPirError::IndexOutOfRange { index, blocks: params.blocks() });
+        }
+
+        let mut results = Vec::with_capacity(indices.len());
+        for indices in indices.chunks(SEED_BATCH_SIZE) {
+            let mut prepared = self.pool.take(indices.len());
+            if prepared.len() < indices.len() {
+                prepared.extend(fetch_prepared(&mut self.connections, indices.len() - prepared.len())?);
+            }
+
+            results.extend(lookup_prepared(&mut self.connections, indices, prepared, self.depth)?);
+        }
+
+        Ok(results)
+    }
+
+    /// Open connections if necessary, and start a new deadline on them.
+    fn ensure_connected(&mut self) -> Result<RaidPirParams> {
+        let deadline = Instant::now() + self.timeout;
+
+        if self.connections.is_empty() {
+            self.connections = self.connect(deadline)?;
+        }
+
+        for connection in self.connections.iter_mut() {
+            connection.set_deadline(Some(deadline));
+        }
+
+        check_params(&self.connections)
+    }
+
+    fn connect(&mut self, deadline: Instant) -> Result<Vec<Connection<C>>> {
+        let connect = &self.connect;
+        let timeout = self.timeout;
+
+        let connections: Vec<Connection<C>> = (0..self.servers)
+            .into_par_iter()
+            .map(|i| {
+                (connect)(i, timeout)
+                    .and_then(|mut transport| {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        transport.set_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+                        Connection::open(transport)
+                    })
+                    .map_err(|e| e.at_server(i))
+            })
+            .with_max_len(1) // Ensure each server gets a thread
+            .collect::<Result<_>>()?;
+
+        let params = connections
+            .first()
+            .map(|c| *c.params())
+            .ok_or(RaidPirError::NoServers)?;
+        for (i, connection) in connections.iter().enumerate() {
+            // Servers that changed parameters since the first round are
+            // rejected, as are servers disagreeing with each other.
+            self.params
+                .get_or_insert(params)
+                .check_fingerprint(connection.params().fingerprint())
+                .map_err(|e| e.at_server(i))?;
+        }
+
+        Ok(connections)
+    }
+}
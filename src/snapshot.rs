@@ -0,0 +1,156 @@
+//! Snapshots of a server's preprocessed seeds, to survive restarts.
+//!
+//! A snapshot file consists of a fixed-size header followed by all queued
+//! seeds and then all handed out seeds, each with its preprocessed answer of
+//! exactly `element_size` bytes:
+//!
+//! ```text
+//! +------------+--------------+-------------+-------------------+--------------+------------------+----------+---------+
+//! | magic: [8] | version: u64 | digest: u64 | element_size: u64 | queued: u64  | handed_out: u64  | reserved | entries |
+//! +------------+--------------+-------------+-------------------+--------------+------------------+----------+---------+
+//! ```
+//!
+//! where each entry is a `u128` seed followed by the answer. All integers are
+//! little-endian, and the header is padded to [HEADER_SIZE] bytes.
+//!
+//! The digest identifies the database (and parameters and server id) the
+//! answers were calculated from, see [crate::server::RaidPirServer::digest].
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::error::{RaidPirError, Result};
+
+/// Magic bytes at the start of every snapshot file.
+pub const MAGIC: &[u8; 8] = b"RPIRSNAP";
+
+/// Version of the snapshot file format.
+pub const SNAPSHOT_VERSION: u64 = 1;
+
+/// Size of the file header in bytes.
+pub const HEADER_SIZE: usize = 64;
+
+/**
+ * Preprocessed seeds of a server, with their answers as raw bytes.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Digest of the database the answers belong to
+    pub digest: u64,
+    /// Size of each answer in bytes
+    pub element_size: usize,
+    /// Seeds in the queue, not handed out yet
+    pub queued: Vec<(u128, Vec<u8>)>,
+    /// Seeds handed out and waiting for their query
+    pub handed_out: Vec<(u128, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /**
+     * Write snapshot to a file. The file is replaced atomically, so an
+     * interrupted write leaves the previous snapshot intact.
+     */
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        if let Some((_, x)) = self
+            .queued
+            .iter()
+            .chain(self.handed_out.iter())
+            .find(|(_, x)| x.len() != self.element_size)
+        {
+            return Err(RaidPirError::ElementSize { expected: self.element_size, actual: x.len() });
+        }
+
+        let mut header = [0; HEADER_SIZE];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        header[16..24].copy_from_slice(&self.digest.to_le_bytes());
+        header[24..32].copy_from_slice(&(self.element_size as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(self.queued.len() as u64).to_le_bytes());
+        header[40..48].copy_from_slice(&(self.handed_out.len() as u64).to_le_bytes());
+
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            writer.write_all(&header)?;
+            for (seed, x) in self.queued.iter().chain(self.handed_out.iter()) {
+                writer.write_all(&seed.to_le_bytes())?;
+                writer.write_all(x)?;
+            }
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /**
+     * Read and validate a snapshot file.
+     */
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..8] != MAGIC {
+            return Err(RaidPirError::InvalidSnapshot("not a RAID-PIR snapshot".to_string()));
+        }
+
+        let field = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+
+        let version = field(8);
+        if version != SNAPSHOT_VERSION {
+            return Err(RaidPirError::InvalidSnapshot(format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+
+        let element_size = field(24);
+        let (queued, handed_out) = (field(32), field(40));
+        let (entries, entry_size) = match (queued.checked_add(handed_out), element_size.checked_add(16)) {
+            (Some(entries), Some(entry_size)) => (entries, entry_size),
+            _ => return Err(RaidPirError::InvalidSnapshot("number or size of entries overflows".to_string())),
+        };
+        let expected = entries
+            .checked_mul(entry_size)
+            .and_then(|x| x.checked_add(HEADER_SIZE as u64));
+        if expected != Some(len) {
+            return Err(RaidPirError::InvalidSnapshot(format!(
+                "file has {} bytes, expected {} entries of {} bytes",
+                len, entries, entry_size
+            )));
+        }
+
+        let element_size = element_size as usize;
+        let mut read_entries = |count: u64| -> Result<Vec<(u128, Vec<u8>)>> {
+            (0..count)
+                .map(|_| {
+                    let mut seed = [0; 16];
+                    reader.read_exact(&mut seed)?;
+                    let mut buffer = vec![0; element_size];
+                    reader.read_exact(&mut buffer)?;
+                    Ok((u128::from_le_bytes(seed), buffer))
+                })
+                .collect()
+        };
+
+        let queued = read_entries(queued)?;
+        let handed_out = read_entries(handed_out)?;
+
+        Ok(Self {
+            digest: field(16),
+            element_size,
+            queued,
+            handed_out,
+        })
+    }
+}
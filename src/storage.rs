@@ -0,0 +1,595 @@
+//! Storage backends for the database a server answers queries from.
+//!
+//! A server only ever reads its database through the [Storage] trait, so the
+//! elements don't have to be kept as a `Vec<T>`. [RaidPirDatabase] stores
+//! all records in a single contiguous buffer instead of one heap allocation
+//! per record, and XORs them into answers straight from that buffer.
+//! [MappedDatabase] serves records straight from a memory-mapped database
+//! file, so the database doesn't have to fit into memory at all.
+
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use bitvec::prelude::*;
+use memmap2::Mmap;
+
+use crate::database::{DatabaseHeader, HEADER_SIZE};
+use crate::error::{RaidPirError, Result};
+use crate::params::RaidPirParams;
+use crate::types::{RaidPirBytes, RaidPirElement};
+
+/**
+ * Read access to the elements of a database.
+ */
+pub trait Storage<T>: Send + Sync {
+    /**
+     * Number of elements stored.
+     */
+    fn len(&self) -> usize;
+
+    /**
+     * Whether no elements are stored.
+     */
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /**
+     * Size of each element in bytes.
+     */
+    fn element_size(&self) -> usize;
+
+    /**
+     * Copy of the element at `index`.
+     */
+    fn get(&self, index: usize) -> T;
+
+    /**
+     * XOR the elements starting at `start` that are selected by `bits` into
+     * `answer`. `start + bits.len()` is never larger than [Storage::len].
+     */
+    fn xor_selected(&self, answer: &mut T, start: usize, bits: &BitSlice<Lsb0, u8>);
+
+    /**
+     * Whether elements can be replaced with [Storage::set]. Read-only storage
+     * returns false, which is the default.
+     */
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /**
+     * Replace the element at `index`, which has the right size. Read-only
+     * storage returns [RaidPirError::ReadOnly], which is the default.
+     */
+    fn set(&mut self, _index: usize, _value: &T) -> Result<()> {
+        Err(RaidPirError::ReadOnly("storage can't be modified".to_string()))
+    }
+}
+
+impl<T: RaidPirElement> Storage<T> for Vec<T> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn element_size(&self) -> usize {
+        self.first().map_or(0, |x| x.element_size())
+    }
+
+    fn get(&self, index: usize) -> T {
+        self[index].clone()
+    }
+
+    fn xor_selected(&self, answer: &mut T, start: usize, bits: &BitSlice<Lsb0, u8>) {
+        bits.iter()
+            .zip(self[start..].iter())
+            .filter(|(q, _)| **q)
+            .for_each(|(_, x)| *answer ^= x);
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn set(&mut self, index: usize, value: &T) -> Result<()> {
+        self[index] = value.clone();
+        Ok(())
+    }
+}
+
+/**
+ * Database of fixed-size records, stored back to back in a single buffer.
+ *
+ * The buffer is 8-byte aligned. Records are XORed into answers directly from
+ * the buffer, without cloning them first, for any [RaidPirBytes] element
+ * type of the same size.
+ *
+ * ```
+ * use raidpir::storage::{RaidPirDatabase, Storage};
+ * use raidpir::types::RaidPirData;
+ *
+ * let mut db = RaidPirDatabase::new(4).unwrap();
+ * db.push(&[1, 2, 3, 4]).unwrap();
+ * db.push(&[5, 6, 7, 8]).unwrap();
+ *
+ * assert!(db.push(&[9]).is_err());
+ * assert_eq!(db.record(1), &[5, 6, 7, 8]);
+ * assert_eq!(Storage::<RaidPirData>::get(&db, 0).data, vec![1, 2, 3, 4]);
+ * ```
+ */
+#[derive(Clone, PartialEq, Eq)]
+pub struct RaidPirDatabase {
+    element_size: usize,
+    len: usize,
+    buffer: Vec<u64>,
+}
+
+impl RaidPirDatabase {
+    /**
+     * Create an empty database for records of `element_size` bytes.
+     */
+    pub fn new(element_size: usize) -> Result<Self> {
+        Self::with_capacity(element_size, 0)
+    }
+
+    /**
+     * Create an empty database with room for `capacity` records.
+     */
+    pub fn with_capacity(element_size: usize, capacity: usize) -> Result<Self> {
+        if element_size == 0 {
+            return Err(RaidPirError::InvalidDatabase("element size must not be 0".to_string()));
+        }
+
+        Ok(Self {
+            element_size,
+            len: 0,
+            buffer: Vec::with_capacity((capacity * element_size).div_ceil(8)),
+        })
+    }
+
+    /**
+     * Create a database from records stored back to back. The length of
+     * `records` needs to be a multiple of `element_size`.
+     */
+    pub fn from_bytes(element_size: usize, records: &[u8]) -> Result<Self> {
+        let mut db = Self::new(element_size)?;
+
+        if records.len() % element_size != 0 {
+            return Err(RaidPirError::InvalidDatabase(format!(
+                "{} bytes are not a multiple of element size {}",
+                records.len(), element_size
+            )));
+        }
+
+        db.extend_bytes(records);
+        db.len = records.len() / element_size;
+
+        Ok(db)
+    }
+
+    /**
+     * Copy the records that server `id` needs out of a whole database, see
+     * [RaidPirParams::window]. The result can be served with
+     * [crate::server::RaidPirServer::with_window].
+     */
+    pub fn from_window<T, S>(storage: &S, id: usize, params: &RaidPirParams) -> Result<Self>
+    where
+        T: RaidPirBytes,
+        S: Storage<T> + ?Sized,
+    {
+        if storage.len() != params.blocks() {
+            return Err(RaidPirError::InvalidParams(format!(
+                "database has {} blocks, expected {}",
+                storage.len(), params.blocks()
+            )));
+        }
+
+        let window = params.window(id);
+        let mut db = Self::with_capacity(params.element_size(), window.iter().map(|x| x.len()).sum())?;
+        let mut buffer = Vec::with_capacity(params.element_size());
+
+        for index in window.into_iter().flatten() {
+            buffer.clear();
+            storage.get(index).write_bytes(&mut buffer);
+            db.push(&buffer)?;
+        }
+
+        Ok(db)
+    }
+
+    /**
+     * Append a record, which needs to be exactly `element_size` bytes long.
+     */
+    pub fn push(&mut self, record: &[u8]) -> Result<()> {
+        if record.len() != self.element_size {
+            return Err(RaidPirError::ElementSize { expected: self.element_size, actual: record.len() });
+        }
+
+        self.extend_bytes(record);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    fn extend_bytes(&mut self, bytes: &[u8]) {
+        let used = self.len * self.element_size;
+        self.buffer.resize((used + bytes.len()).div_ceil(8), 0);
+        bytemuck::cast_slice_mut::<u64, u8>(&mut self.buffer)[used..used + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /**
+     * Replace the record at `index`.
+     */
+    pub fn set_record(&mut self, index: usize, record: &[u8]) -> Result<()> {
+        if index >= self.len {
+            return Err(RaidPirError::IndexOutOfRange { index, blocks: self.len });
+        }
+
+        if record.len() != self.element_size {
+            return Err(RaidPirError::ElementSize { expected: self.element_size, actual: record.len() });
+        }
+
+        let start = index * self.element_size;
+        bytemuck::cast_slice_mut::<u64, u8>(&mut self.buffer)[start..start + record.len()].copy_from_slice(record);
+
+        Ok(())
+    }
+
+    /**
+     * Size of each record in bytes.
+     */
+    pub fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    /**
+     * Number of records.
+     */
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /**
+     * Whether there are no records.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * All records, back to back.
+     */
+    pub fn as_bytes(&self) -> &[u8] {
+        &bytemuck::cast_slice(&self.buffer)[..self.len * self.element_size]
+    }
+
+    /**
+     * Bytes of the record at `index`.
+     */
+    pub fn record(&self, index: usize) -> &[u8] {
+        &self.as_bytes()[index * self.element_size..(index + 1) * self.element_size]
+    }
+}
+
+impl fmt::Debug for RaidPirDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RaidPirDatabase")
+            .field("element_size", &self.element_size)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<T: RaidPirBytes> Storage<T> for RaidPirDatabase {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    fn get(&self, index: usize) -> T {
+        T::from_bytes(self.record(index))
+    }
+
+    fn xor_selected(&self, answer: &mut T, start: usize, bits: &BitSlice<Lsb0, u8>) {
+        let records = self.as_bytes()[start * self.element_size..].chunks_exact(self.element_size);
+
+        bits.iter()
+            .zip(records)
+            .filter(|(q, _)| **q)
+            .for_each(|(_, x)| answer.xor_bytes(x));
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    fn set(&mut self, index: usize, value: &T) -> Result<()> {
+        let mut buffer = Vec::with_capacity(self.element_size);
+        value.write_bytes(&mut buffer);
+        self.set_record(index, &buffer)
+    }
+}
+
+/**
+ * Database file in the format of [crate::database], memory-mapped instead of
+ * read into memory.
+ *
+ * Records are paged in by the operating system as they are accessed, so the
+ * file can be larger than the available memory. The records can't be
+ * updated through the mapping.
+ *
+ * The file must not be modified or truncated by anyone while it is mapped.
+ * Doing so is undefined behaviour, and truncation crashes the process with
+ * SIGBUS. Replace served files by renaming a new file over them instead.
+ */
+#[derive(Debug)]
+pub struct MappedDatabase {
+    map: Mmap,
+    offset: usize,
+    element_size: usize,
+    len: usize,
+}
+
+impl MappedDatabase {
+    /**
+     * Map a database file with header, see [crate::database].
+     */
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let map = map_file(&File::open(path)?)?;
+        let header = DatabaseHeader::from_bytes(&map)?;
+
+        let expected = header.file_size()?;
+        if map.len() as u64 != expected {
+            return Err(RaidPirError::InvalidDatabase(format!(
+                "file has {} bytes, expected {} for {} records of {} bytes",
+                map.len(), expected, header.blocks, header.element_size
+            )));
+        }
+
+        Ok(Self {
+            map,
+            offset: HEADER_SIZE,
+            element_size: header.element_size,
+            len: header.blocks,
+        })
+    }
+
+    /**
+     * Map a headerless file consisting only of records of the given size.
+     */
+    pub fn open_raw<P: AsRef<Path>>(path: P, element_size: usize) -> Result<Self> {
+        if element_size == 0 {
+            return Err(RaidPirError::InvalidDatabase("element size must not be 0".to_string()));
+        }
+
+        let map = map_file(&File::open(path)?)?;
+        if map.len() % element_size != 0 {
+            return Err(RaidPirError::InvalidDatabase(format!(
+                "file size {} is not a multiple of element size {}",
+                map.len(), element_size
+            )));
+        }
+
+        Ok(Self {
+            len: map.len() / element_size,
+            map,
+            offset: 0,
+            element_size,
+        })
+    }
+
+    /**
+     * Size of each record in bytes.
+     */
+    pub fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    /**
+     * Number of records.
+     */
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /**
+     * Whether there are no records.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * Bytes of the record at `index`.
+     */
+    pub fn record(&self, index: usize) -> &[u8] {
+        let start = self.offset + index * self.element_size;
+        &self.map[start..start + self.element_size]
+    }
+}
+
+impl<T: RaidPirBytes> Storage<T> for MappedDatabase {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn element_size(&self) -> usize {
+        self.element_size
+    }
+
+    fn get(&self, index: usize) -> T {
+        T::from_bytes(self.record(index))
+    }
+
+    fn xor_selected(&self, answer: &mut T, start: usize, bits: &BitSlice<Lsb0, u8>) {
+        let records = self.map[self.offset + start * self.element_size..].chunks_exact(self.element_size);
+
+        bits.iter()
+            .zip(records)
+            .filter(|(q, _)| **q)
+            .for_each(|(_, x)| answer.xor_bytes(x));
+    }
+}
+
+/// Map a file read-only. This is the only unsafe code in the crate.
+#[allow(unsafe_code)]
+pub(crate) fn map_file(file: &File) -> Result<Mmap> {
+    // SAFETY: the returned slice is only sound as long as nobody modifies or
+    // truncates the file while it is mapped. The crate itself never does:
+    // the mapping is read-only, and tables and snapshots are replaced by
+    // renaming. Other processes, however, can't be prevented from changing
+    // the file. If they do, reads see torn data (undefined behaviour in
+    // Rust's model) or, after truncation, the process is killed by SIGBUS.
+    // This is why callers, the crate docs and the README require mapped
+    // files to stay unchanged while they are served.
+    Ok(unsafe { Mmap::map(file)? })
+}
+
+/// A server's view of its database: the [RaidPirParams::window_len] blocks
+/// starting at its own chunk, with padding as default elements.
+///
+/// The storage either holds the whole unpadded database, which is then
+/// rotated by index arithmetic, or only the records in the window, see
+/// [RaidPirParams::window]. Either way, the window consists of the records
+/// up to `first`, then `gap` blocks of padding, then the remaining records up
+/// to `end`, then padding again if the window wraps around past all records.
+pub(crate) struct View<T> {
+    storage: Arc<dyn Storage<T>>,
+    start: usize,
+    padded: usize,
+    base: usize,
+    first: usize,
+    gap: usize,
+    end: usize,
+    len: usize,
+}
+
+impl<T: RaidPirElement> View<T> {
+    /// View of server `id` into either the whole database, or only its
+    /// window if `windowed` is set.
+    pub(crate) fn new(storage: Arc<dyn Storage<T>>, id: usize, params: &RaidPirParams, windowed: bool) -> Self {
+        let start = id * params.blocks_per_server();
+        let len = params.window_len();
+        let first = params.blocks().saturating_sub(start).min(len);
+        let gap = (params.blocks_padded() - start.max(params.blocks())).min(len - first);
+        let stored: usize = params.window(id).iter().map(|x| x.len()).sum();
+
+        let base = match windowed || start >= params.blocks() {
+            true => 0,
+            false => start,
+        };
+
+        Self {
+            storage,
+            start,
+            padded: params.blocks_padded(),
+            base,
+            first,
+            gap,
+            end: gap + stored,
+            len,
+        }
+    }
+
+    /// Number of elements, including padding.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Index into the storage of the element at `index`, or `None` in the
+    /// padding.
+    fn storage_index(&self, index: usize) -> Option<usize> {
+        let index = match index < self.first {
+            true => index,
+            false if index < self.first + self.gap || index >= self.end => return None,
+            false => index - self.gap,
+        };
+
+        Some((self.base + index) % self.storage.len())
+    }
+
+    /// Position in the view of the record at `index` in the unpadded
+    /// database, or `None` if it is outside the server's window.
+    pub(crate) fn position(&self, index: usize) -> Option<usize> {
+        let position = (index + self.padded - self.start) % self.padded;
+        match position < self.len {
+            true => Some(position),
+            false => None,
+        }
+    }
+
+    /// Check that elements can be replaced, i.e. the storage is writable and
+    /// not shared with other servers.
+    pub(crate) fn check_writable(&mut self) -> Result<()> {
+        match Arc::get_mut(&mut self.storage) {
+            Some(storage) if storage.is_writable() => Ok(()),
+            Some(_) => Err(RaidPirError::ReadOnly("storage can't be modified".to_string())),
+            None => Err(RaidPirError::ReadOnly("database is shared with other servers".to_string())),
+        }
+    }
+
+    /// Replace the element at `position`, which must not be in the padding.
+    /// Fails if the storage is read-only or shared with other servers.
+    pub(crate) fn set(&mut self, position: usize, value: &T) -> Result<()> {
+        let index = self.storage_index(position).expect("position in the padding");
+
+        match Arc::get_mut(&mut self.storage) {
+            Some(storage) => storage.set(index, value),
+            None => Err(RaidPirError::ReadOnly("database is shared with other servers".to_string())),
+        }
+    }
+
+    /// Element at `index`, which is a default element in the padding.
+    pub(crate) fn get(&self, index: usize) -> T {
+        match self.storage_index(index) {
+            Some(index) => self.storage.get(index),
+            None => T::default(),
+        }
+    }
+
+    /// XOR the elements starting at `start` that are selected by `bits` into
+    /// `answer`.
+    pub(crate) fn xor_selected(&self, answer: &mut T, start: usize, bits: &BitSlice<Lsb0, u8>) {
+        let mut bits = bits;
+        let mut index = start;
+
+        while !bits.is_empty() {
+            // Up to the padding, the end of the window, or to where the
+            // storage wraps around. Padding is skipped, XORing it in would be
+            // a no-op.
+            let count = match self.storage_index(index) {
+                Some(position) => {
+                    let end = if index < self.first { self.first } else { self.end };
+                    let count = (end - index).min(self.storage.len() - position).min(bits.len());
+                    self.storage.xor_selected(answer, position, &bits[..count]);
+                    count
+                }
+                None if index < self.first + self.gap => (self.first + self.gap - index).min(bits.len()),
+                None => (self.len - index).min(bits.len()),
+            };
+
+            bits = &bits[count..];
+            index += count;
+        }
+    }
+}
+
+impl<T> fmt::Debug for View<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("View")
+            .field("stored", &self.storage.len())
+            .field("start", &self.start)
+            .field("padded", &self.padded)
+            .field("base", &self.base)
+            .field("first", &self.first)
+            .field("gap", &self.gap)
+            .field("end", &self.end)
+            .field("len", &self.len)
+            .finish()
+    }
+}
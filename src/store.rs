@@ -0,0 +1,452 @@
+//! Stores for seeds handed out to clients, waiting for their query.
+//!
+//! A server keeps the preprocessed part of the answer for every seed it hands
+//! out in a [SeedStore]. By default this is a [MemoryStore] private to the
+//! server. Several processes serving the same server id can share a
+//! [FileStore] instead, so a query can be answered by any of them, no matter
+//! which one handed out the seed.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::{RaidPirError, Result};
+use crate::types::RaidPirBytes;
+
+/// Default minimum time between two evictions of a [FileStore].
+pub const DEFAULT_EVICT_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * Store for the preprocessed answers of seeds handed out to clients.
+ *
+ * Every seed is removed at most once, so that each seed can only be used for
+ * a single response, even when several servers share a store.
+ */
+pub trait SeedStore<T>: fmt::Debug + Send + Sync {
+    /**
+     * Store the preprocessed answer for a seed that was just handed out.
+     */
+    fn insert(&self, seed: u128, answer: T) -> Result<()>;
+
+    /**
+     * Remove a seed, returning its preprocessed answer if it was stored.
+     */
+    fn remove(&self, seed: u128) -> Result<Option<T>>;
+
+    /**
+     * Whether a seed is stored, i.e. was neither removed nor evicted yet.
+     */
+    fn contains(&self, seed: u128) -> Result<bool>;
+
+    /**
+     * Evict seeds handed out longer than `ttl` ago, and the oldest seeds
+     * beyond `limit`. Returns the number of evicted seeds.
+     */
+    fn evict(&self, ttl: Option<Duration>, limit: Option<usize>) -> Result<usize>;
+
+    /**
+     * Patch the answers of all stored seeds after the database changed.
+     * `patch` is called with every seed and its answer, and returns whether
+     * it changed the answer. Returns the number of changed answers.
+     */
+    fn patch(&self, patch: &mut dyn FnMut(u128, &mut T) -> bool) -> Result<usize>;
+
+    /**
+     * Whether [SeedStore::patch] is supported. Stores shared by several
+     * servers can't be patched, since each of them would patch the same
+     * answers again. Defaults to `true`.
+     */
+    fn is_patchable(&self) -> bool {
+        true
+    }
+
+    /**
+     * Record that the stateless seed `seed` was answered (see
+     * [crate::seeds]). Returns `false` if it was recorded before. Records
+     * may be evicted like seeds once they are older than the seed TTL.
+     *
+     * Stores private to a single server record nothing and return `true`,
+     * which is the default, since the server's [crate::seeds::SeedIssuer]
+     * remembers the seeds it redeemed itself.
+     */
+    fn redeem(&self, _seed: u128) -> Result<bool> {
+        Ok(true)
+    }
+
+    /**
+     * Copy of all stored seeds and their answers, e.g. to save them in a
+     * [crate::snapshot::Snapshot]. Stores that persist seeds by themselves
+     * return nothing, which is the default.
+     */
+    fn snapshot(&self) -> Result<Vec<(u128, T)>> {
+        Ok(Vec::new())
+    }
+
+    /**
+     * Number of seeds currently stored.
+     */
+    fn len(&self) -> Result<usize>;
+
+    /**
+     * Whether no seeds are currently stored.
+     */
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/**
+ * In-memory seed store, private to a single process. This is the default.
+ */
+pub struct MemoryStore<T> {
+    inner: Mutex<HandedOut<T>>,
+}
+
+struct HandedOut<T> {
+    entries: HashMap<u128, (Instant, T)>,
+    /// Seeds in the order they were handed out. May contain seeds that have
+    /// been answered already, those are skipped.
+    order: VecDeque<(Instant, u128)>,
+}
+
+impl<T> Default for MemoryStore<T> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(HandedOut {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+}
+
+impl<T> MemoryStore<T> {
+    /**
+     * Create an empty store.
+     */
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Elements need not be Debug, so only show how many seeds are stored.
+impl<T> fmt::Debug for MemoryStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.inner.lock().map(|inner| inner.entries.len()).ok();
+        f.debug_struct("MemoryStore").field("len", &len).finish()
+    }
+}
+
+impl<T: Clone + Send> SeedStore<T> for MemoryStore<T> {
+    fn insert(&self, seed: u128, answer: T) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let issued = Instant::now();
+        inner.entries.insert(seed, (issued, answer));
+        inner.order.push_back((issued, seed));
+
+        // Without any limits, answered seeds would pile up in the back.
+        if inner.order.len() > 2 * inner.entries.len() + 32 {
+            let HandedOut { entries, order } = &mut *inner;
+            order.retain(|(issued, seed)| entries.get(seed).map(|e| e.0) == Some(*issued));
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, seed: u128) -> Result<Option<T>> {
+        Ok(self.inner.lock().unwrap().entries.remove(&seed).map(|(_, answer)| answer))
+    }
+
+    fn contains(&self, seed: u128) -> Result<bool> {
+        Ok(self.inner.lock().unwrap().entries.contains_key(&seed))
+    }
+
+    fn evict(&self, ttl: Option<Duration>, limit: Option<usize>) -> Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        let cutoff = ttl.and_then(|ttl| Instant::now().checked_sub(ttl));
+        let mut evicted = 0;
+
+        while let Some(&(issued, seed)) = inner.order.front() {
+            let current = inner.entries.get(&seed).map(|e| e.0) == Some(issued);
+            let expired = cutoff.is_some_and(|cutoff| issued < cutoff);
+            let excess = limit.is_some_and(|limit| inner.entries.len() > limit);
+
+            if current && !expired && !excess {
+                break;
+            }
+
+            inner.order.pop_front();
+            if current {
+                inner.entries.remove(&seed);
+                evicted += 1;
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    fn patch(&self, patch: &mut dyn FnMut(u128, &mut T) -> bool) -> Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+
+        Ok(inner
+            .entries
+            .iter_mut()
+            .map(|(seed, (_, answer))| patch(*seed, answer))
+            .filter(|changed| *changed)
+            .count())
+    }
+
+    fn snapshot(&self) -> Result<Vec<(u128, T)>> {
+        let inner = self.inner.lock().unwrap();
+
+        // In the order they were handed out, so evictions stay oldest-first
+        // after restoring.
+        Ok(inner
+            .order
+            .iter()
+            .filter_map(|(issued, seed)| match inner.entries.get(seed) {
+                Some((i, answer)) if i == issued => Some((*seed, answer.clone())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.inner.lock().unwrap().entries.len())
+    }
+}
+
+/**
+ * Seed store backed by a directory, shared by all processes using it.
+ *
+ * Each seed is stored in its own file, named after the seed. Files are
+ * written under a temporary name and renamed into place, and claimed by
+ * renaming them away again before reading, so every seed is answered by
+ * exactly one process. Stateless seeds are redeemed by creating a marker
+ * file, so each of them is answered only once as well. Putting the
+ * directory on a tmpfs (e.g. `/dev/shm`) avoids touching the disk.
+ *
+ * Scanning the directory is comparatively expensive, so evictions happen at
+ * most once per [DEFAULT_EVICT_INTERVAL], and `limit` may be exceeded in
+ * between. See [FileStore::set_evict_interval].
+ *
+ * Answers in a shared store can't be patched when the database changes,
+ * since every server sharing it would patch them again. Servers using a
+ * [FileStore] therefore reject updates, see
+ * [crate::server::RaidPirServer::update_batch].
+ */
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+    element_size: usize,
+    counter: AtomicU64,
+    last_evict: Mutex<Option<Instant>>,
+    evict_interval: Duration,
+}
+
+impl FileStore {
+    /**
+     * Open a store in the given directory, creating it if needed. Answers
+     * read back from it must be `element_size` bytes long.
+     */
+    pub fn open<P: AsRef<Path>>(dir: P, element_size: usize) -> Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            element_size,
+            counter: AtomicU64::new(0),
+            last_evict: Mutex::new(None),
+            evict_interval: DEFAULT_EVICT_INTERVAL,
+        })
+    }
+
+    /**
+     * Directory the store is kept in.
+     */
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /**
+     * Set minimum time between two evictions.
+     */
+    pub fn set_evict_interval(&mut self, interval: Duration) {
+        self.evict_interval = interval;
+    }
+
+    fn path(&self, seed: u128) -> PathBuf {
+        self.dir.join(format!("{:032x}", seed))
+    }
+
+    /// Unique path for files not (yet) visible as seeds. These start with a
+    /// dot, so they are never mistaken for one.
+    fn private_path(&self, seed: u128, suffix: &str) -> PathBuf {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        self.dir.join(format!(".{:032x}.{}.{}.{}", seed, std::process::id(), counter, suffix))
+    }
+
+    /// Decode an answer read back from a file, which might have been
+    /// truncated or written by a server with a different database.
+    fn decode<T: RaidPirBytes>(&self, bytes: Vec<u8>) -> Result<T> {
+        if bytes.len() != self.element_size {
+            return Err(RaidPirError::ElementSize {
+                expected: self.element_size,
+                actual: bytes.len(),
+            });
+        }
+
+        Ok(T::from_bytes(&bytes))
+    }
+
+    /// All seeds in the store with their modification times. Private files
+    /// are returned with a seed of `None`.
+    fn entries(&self) -> Result<Vec<(Option<u128>, PathBuf, SystemTime)>> {
+        let mut entries = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            let modified = match entry.metadata().and_then(|m| m.modified()) {
+                Ok(modified) => modified,
+                // Claimed by someone else in the meantime.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let seed = if name.starts_with('.') {
+                None
+            } else if let Ok(seed) = u128::from_str_radix(&name, 16) {
+                Some(seed)
+            } else {
+                continue;
+            };
+
+            entries.push((seed, entry.path(), modified));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Remove a file, ignoring files removed by someone else in the meantime.
+/// Returns whether the file was removed by us.
+fn remove_file(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl<T: RaidPirBytes> SeedStore<T> for FileStore {
+    fn insert(&self, seed: u128, answer: T) -> Result<()> {
+        let tmp = self.private_path(seed, "tmp");
+        fs::write(&tmp, answer.to_bytes())?;
+
+        if let Err(e) = fs::rename(&tmp, self.path(seed)) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, seed: u128) -> Result<Option<T>> {
+        // Only one process can win the rename.
+        let claimed = self.private_path(seed, "claim");
+        match fs::rename(self.path(seed), &claimed) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let answer = fs::read(&claimed);
+        let _ = fs::remove_file(&claimed);
+
+        self.decode(answer?).map(Some)
+    }
+
+    fn redeem(&self, seed: u128) -> Result<bool> {
+        // Only one process can create the marker. It is private, so it is
+        // evicted like files left behind by crashed processes.
+        let marker = self.dir.join(format!(".{:032x}.redeemed", seed));
+        match fs::OpenOptions::new().write(true).create_new(true).open(marker) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn contains(&self, seed: u128) -> Result<bool> {
+        match fs::metadata(self.path(seed)) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn evict(&self, ttl: Option<Duration>, limit: Option<usize>) -> Result<usize> {
+        if ttl.is_none() && limit.is_none() {
+            return Ok(0);
+        }
+
+        {
+            let mut last_evict = self.last_evict.lock().unwrap();
+            if last_evict.is_some_and(|last| last.elapsed() < self.evict_interval) {
+                return Ok(0);
+            }
+            *last_evict = Some(Instant::now());
+        }
+
+        let cutoff = ttl.and_then(|ttl| SystemTime::now().checked_sub(ttl));
+        let expired = |modified: &SystemTime| cutoff.is_some_and(|cutoff| *modified < cutoff);
+
+        let mut seeds = Vec::new();
+        let mut evicted = 0;
+
+        for (seed, path, modified) in self.entries()? {
+            match seed {
+                Some(_) if expired(&modified) => evicted += remove_file(&path)? as usize,
+                Some(_) => seeds.push((modified, path)),
+                // Left behind by a crashed process.
+                None if expired(&modified) => {
+                    remove_file(&path)?;
+                }
+                None => {}
+            }
+        }
+
+        if let Some(limit) = limit {
+            if seeds.len() > limit {
+                seeds.sort();
+                let excess = seeds.len() - limit;
+                for (_, path) in seeds.iter().take(excess) {
+                    evicted += remove_file(path)? as usize;
+                }
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    fn patch(&self, _patch: &mut dyn FnMut(u128, &mut T) -> bool) -> Result<usize> {
+        Err(RaidPirError::ReadOnly("handed out seeds are shared with other servers".to_string()))
+    }
+
+    fn is_patchable(&self) -> bool {
+        false
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.entries()?.iter().filter(|(seed, _, _)| seed.is_some()).count())
+    }
+}
@@ -0,0 +1,251 @@
+//! Transports for exchanging [Message]s between clients and servers.
+//!
+//! The lookup logic in [crate::service] is written against the [Transport]
+//! trait, so the same code path can be used over TCP, Unix domain sockets or
+//! in-process channels (e.g. in tests).
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+
+use crate::error::{RaidPirError, Result};
+use crate::protocol::{decode, encode, read_message, write_message, Message};
+
+/**
+ * Bidirectional, message-oriented connection between client and server.
+ */
+pub trait Transport {
+    /**
+     * Send a single message.
+     */
+    fn send(&mut self, msg: &Message) -> Result<()>;
+
+    /**
+     * Receive a single message, blocking until one is available.
+     */
+    fn recv(&mut self) -> Result<Message>;
+
+    /**
+     * Set timeout for subsequent sends and receives. `None` blocks
+     * indefinitely.
+     */
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+}
+
+impl<C: Transport + ?Sized> Transport for &mut C {
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        (**self).send(msg)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        (**self).recv()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        (**self).set_timeout(timeout)
+    }
+}
+
+impl<C: Transport + ?Sized> Transport for Box<C> {
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        (**self).send(msg)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        (**self).recv()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        (**self).set_timeout(timeout)
+    }
+}
+
+/**
+ * Byte streams that support read and write timeouts, and can thus be used
+ * with [StreamTransport].
+ */
+pub trait TimeoutStream: Read + Write {
+    /**
+     * Set read and write timeout. `None` blocks indefinitely.
+     */
+    fn set_timeouts(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl TimeoutStream for TcpStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl TimeoutStream for UnixStream {
+    fn set_timeouts(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+/**
+ * Transport over a byte stream, using the framing from [crate::protocol].
+ */
+#[derive(Debug)]
+pub struct StreamTransport<S> {
+    stream: S,
+}
+
+/// Transport over a TCP connection.
+pub type TcpTransport = StreamTransport<TcpStream>;
+
+/// Transport over a Unix domain socket.
+#[cfg(unix)]
+pub type UnixTransport = StreamTransport<UnixStream>;
+
+impl<S: TimeoutStream> StreamTransport<S> {
+    /**
+     * Wrap an existing stream.
+     */
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /**
+     * Returns reference to the underlying stream.
+     */
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /**
+     * Unwrap the underlying stream.
+     */
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl StreamTransport<TcpStream> {
+    /**
+     * Connect to the given address, using `timeout` for connecting as well
+     * as all subsequent reads and writes.
+     */
+    pub fn connect<A: ToSocketAddrs>(address: A, timeout: Duration) -> Result<Self> {
+        let mut last_error = None;
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_timeouts(Some(timeout))?;
+                    return Ok(Self::new(stream));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "address did not resolve"))
+            .into())
+    }
+}
+
+#[cfg(unix)]
+impl StreamTransport<UnixStream> {
+    /**
+     * Connect to the socket at the given path, using `timeout` for all reads
+     * and writes.
+     */
+    pub fn connect<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_timeouts(Some(timeout))?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: TimeoutStream> Transport for StreamTransport<S> {
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        write_message(&mut self.stream, msg)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        read_message(&mut self.stream)
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.stream.set_timeouts(timeout)?)
+    }
+}
+
+/**
+ * In-process transport over a pair of channels.
+ *
+ * Messages are still encoded into frames, so this exercises the same code
+ * path as network transports.
+ *
+ * ```
+ * use raidpir::protocol::Message;
+ * use raidpir::transport::{ChannelTransport, Transport};
+ *
+ * let (mut client, mut server) = ChannelTransport::pair();
+ * client.send(&Message::SeedGrant(42)).unwrap();
+ *
+ * assert_eq!(server.recv().unwrap(), Message::SeedGrant(42));
+ * ```
+ */
+#[derive(Debug)]
+pub struct ChannelTransport {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    timeout: Option<Duration>,
+}
+
+impl ChannelTransport {
+    /**
+     * Create a pair of connected transports.
+     */
+    pub fn pair() -> (Self, Self) {
+        let (a_sender, b_receiver) = channel();
+        let (b_sender, a_receiver) = channel();
+
+        (
+            Self { sender: a_sender, receiver: a_receiver, timeout: None },
+            Self { sender: b_sender, receiver: b_receiver, timeout: None },
+        )
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, msg: &Message) -> Result<()> {
+        self.sender
+            .send(encode(msg))
+            .map_err(|_| std::io::Error::new(ErrorKind::BrokenPipe, "channel closed").into())
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        let frame = match self.timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => std::io::Error::new(ErrorKind::TimedOut, "channel timed out"),
+                RecvTimeoutError::Disconnected => std::io::Error::new(ErrorKind::UnexpectedEof, "channel closed"),
+            }),
+            None => self
+                .receiver
+                .recv()
+                .map_err(|_| std::io::Error::new(ErrorKind::UnexpectedEof, "channel closed")),
+        };
+
+        frame
+            .map_err(RaidPirError::from)
+            .and_then(|frame| decode(&frame))
+    }
+
+    /// Sending never blocks, so this only applies to receiving.
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
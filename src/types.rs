@@ -1,7 +1,94 @@
 //! Associated RAID-PIR types
 
+use std::convert::TryInto;
 use std::ops::{BitXor, BitXorAssign};
 
+/**
+ * Trait for types that can be used as RAID-PIR database elements.
+ *
+ * Elements need to be bit-xor-assignable (also from references, to avoid
+ * cloning every element that is XORed in) and have a default value that acts
+ * as the neutral element for XOR. They are shared between threads during
+ * preprocessing, and kept in seed stores (see [crate::store]), so they can't
+ * borrow data.
+ */
+pub trait RaidPirElement:
+    Clone + Default + Send + Sync + 'static + BitXor<Output = Self> + BitXorAssign + for<'a> BitXorAssign<&'a Self>
+{
+    /**
+     * Size of this element in bytes. All elements of a database, as well as
+     * all responses to a query, are expected to have the same size.
+     */
+    fn element_size(&self) -> usize;
+
+    /**
+     * Neutral element for XOR with a size of `element_size` bytes. The
+     * default is [Default::default], which is only right for elements of a
+     * fixed size.
+     */
+    fn zero(_element_size: usize) -> Self {
+        Self::default()
+    }
+}
+
+/**
+ * Database elements with a fixed byte representation, which can be stored in
+ * files and XORed with raw bytes directly, without decoding them first.
+ */
+pub trait RaidPirBytes: RaidPirElement {
+    /**
+     * Append the bytes of this element to `out`.
+     */
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    /**
+     * Decode an element from exactly [RaidPirElement::element_size] bytes.
+     */
+    fn from_bytes(bytes: &[u8]) -> Self;
+
+    /**
+     * XOR the element encoded in `bytes` into this one.
+     */
+    fn xor_bytes(&mut self, bytes: &[u8]);
+
+    /**
+     * Encode this element into a new buffer.
+     */
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.element_size());
+        self.write_bytes(&mut out);
+        out
+    }
+}
+
+macro_rules! impl_element_for_int {
+    ($($t:ty),*) => {
+        $(
+            impl RaidPirElement for $t {
+                fn element_size(&self) -> usize {
+                    std::mem::size_of::<$t>()
+                }
+            }
+
+            impl RaidPirBytes for $t {
+                fn write_bytes(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().unwrap())
+                }
+
+                fn xor_bytes(&mut self, bytes: &[u8]) {
+                    *self ^= Self::from_bytes(bytes);
+                }
+            }
+        )*
+    };
+}
+
+impl_element_for_int!(u8, u16, u32, u64, u128, usize);
+
 /**
  * Type for arbitrarily-sized byte arrays used as RAID-PIR database elements.
  */
@@ -55,6 +142,16 @@ impl BitXor for RaidPirData {
 
 impl BitXorAssign for RaidPirData {
     fn bitxor_assign(&mut self, rhs: Self) {
+        *self ^= &rhs;
+    }
+}
+
+impl BitXorAssign<&RaidPirData> for RaidPirData {
+    fn bitxor_assign(&mut self, rhs: &Self) {
+        if self.data.len() < rhs.data.len() {
+            self.data.resize(rhs.data.len(), 0);
+        }
+
         self.data.iter_mut().zip(rhs.data.iter()).for_each(|(a,b)| {
             *a ^= b;
         });
@@ -70,8 +167,44 @@ impl IntoIterator for RaidPirData {
     }
 }
 
-impl Into<Vec<u8>> for RaidPirData {
-    fn into(self) -> Vec<u8> {
-        self.data
+impl From<RaidPirData> for Vec<u8> {
+    fn from(data: RaidPirData) -> Self {
+        data.data
+    }
+}
+
+impl From<Vec<u8>> for RaidPirData {
+    fn from(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl RaidPirElement for RaidPirData {
+    fn element_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn zero(element_size: usize) -> Self {
+        Self::new(vec![0; element_size])
+    }
+}
+
+impl RaidPirBytes for RaidPirData {
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.data);
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self::new(bytes.to_vec())
+    }
+
+    fn xor_bytes(&mut self, bytes: &[u8]) {
+        if self.data.len() < bytes.len() {
+            self.data.resize(bytes.len(), 0);
+        }
+
+        self.data.iter_mut().zip(bytes.iter()).for_each(|(a, b)| {
+            *a ^= b;
+        });
     }
 }
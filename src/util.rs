@@ -7,6 +7,24 @@ use rand::{RngCore, SeedableRng};
 use rand_chacha::ChaChaRng;
 use rayon::prelude::*;
 
+/// Offset basis of 64-bit FNV-1a, i.e. the hash of no bytes.
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/**
+ * Continue a 64-bit FNV-1a hash over `bytes`, starting from [FNV_OFFSET].
+ *
+ * ```
+ * use raidpir::util::{fnv1a, FNV_OFFSET};
+ *
+ * assert_eq!(fnv1a(fnv1a(FNV_OFFSET, b"ab"), b"c"), fnv1a(FNV_OFFSET, b"abc"));
+ * ```
+ */
+pub fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
 /**
  * Generate a BitVec of random data with the given size and seed.
  *
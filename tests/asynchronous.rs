@@ -0,0 +1,120 @@
+#![cfg(feature = "async")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+use raidpir::asynchronous::{lookup, read_message, serve};
+use raidpir::error::RaidPirError;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::Message;
+use raidpir::server::RaidPirServer;
+
+mod common;
+use common::{random_db, servers};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lookup() {
+    let db = random_db(500, 32);
+    let params = RaidPirParams::new(db.len(), 4, 3, 32).unwrap();
+
+    let mut addresses = Vec::new();
+    let mut shutdowns = Vec::new();
+    let mut handles = Vec::new();
+
+    for server in servers(&db, params) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addresses.push(listener.local_addr().unwrap().to_string());
+
+        let (sender, receiver) = oneshot::channel::<()>();
+        shutdowns.push(sender);
+        handles.push(tokio::spawn(serve(listener, server, Duration::from_secs(10), async {
+            receiver.await.ok();
+        })));
+    }
+
+    for index in [0, 123, 499] {
+        let record = lookup(&addresses, index, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(record.as_slice(), db[index].as_slice());
+    }
+
+    assert!(matches!(
+        lookup(&addresses, 500, Duration::from_secs(10)).await,
+        Err(RaidPirError::IndexOutOfRange { .. })
+    ));
+
+    assert!(matches!(
+        lookup::<String>(&[], 0, Duration::from_secs(10)).await,
+        Err(RaidPirError::NoServers)
+    ));
+
+    for sender in shutdowns {
+        sender.send(()).unwrap();
+    }
+
+    for handle in handles {
+        handle.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_timeout() {
+    let db = random_db(64, 4);
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+
+    let server = Arc::new(RaidPirServer::new(db, 0, params, false).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let good = listener.local_addr().unwrap().to_string();
+    tokio::spawn(serve(listener, server, Duration::from_secs(10), std::future::pending()));
+
+    // Accepts connections, but never says anything.
+    let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let bad = silent.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        loop {
+            streams.push(silent.accept().await.unwrap());
+        }
+    });
+
+    let start = std::time::Instant::now();
+    let result = lookup(&[good, bad], 0, Duration::from_millis(200)).await;
+
+    match result {
+        Err(RaidPirError::Server { server: 1, error }) => {
+            assert!(matches!(*error, RaidPirError::Io(e) if e.kind() == std::io::ErrorKind::TimedOut))
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_idle_timeout() {
+    let db = random_db(64, 4);
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+
+    let server = Arc::new(RaidPirServer::new(db, 0, params, false).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let (sender, receiver) = oneshot::channel::<()>();
+    let handle = tokio::spawn(serve(listener, server, Duration::from_millis(200), async {
+        receiver.await.ok();
+    }));
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    assert!(matches!(read_message(&mut stream).await.unwrap(), Message::Hello(_)));
+    assert!(matches!(read_message(&mut stream).await.unwrap(), Message::SeedGrant(_)));
+
+    // Never send anything, the server should hang up on us.
+    let start = std::time::Instant::now();
+    assert!(matches!(read_message(&mut stream).await, Err(RaidPirError::Io(_))));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // Shutdown doesn't wait for the idle client either.
+    sender.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), handle).await.unwrap().unwrap().unwrap();
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the integration tests.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use raidpir::params::RaidPirParams;
+use raidpir::server::RaidPirServer;
+use raidpir::types::RaidPirData;
+
+/// Database of `blocks` random elements of `element_size` bytes each.
+pub fn random_db(blocks: usize, element_size: usize) -> Vec<RaidPirData> {
+    let mut prng = StdRng::from_entropy();
+
+    (0..blocks)
+        .map(|_| {
+            let mut buffer = vec![0; element_size];
+            prng.fill_bytes(&mut buffer);
+            RaidPirData::new(buffer)
+        })
+        .collect()
+}
+
+/// One server per id, all sharing a single copy of `db`.
+pub fn servers(db: &[RaidPirData], params: RaidPirParams) -> Vec<Arc<RaidPirServer<RaidPirData>>> {
+    let db = Arc::new(db.to_vec());
+    (0..params.servers())
+        .map(|i| Arc::new(RaidPirServer::with_shared(db.clone(), i, params, true).unwrap()))
+        .collect()
+}
@@ -0,0 +1,74 @@
+use std::io::Write;
+
+use raidpir::database::*;
+use raidpir::error::RaidPirError;
+use raidpir::storage::MappedDatabase;
+use raidpir::types::RaidPirData;
+
+#[test]
+fn test_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.raidpir");
+
+    let db: Vec<RaidPirData> = (0..100u32)
+        .map(|i| RaidPirData::new([i.to_le_bytes(), (i * 3).to_le_bytes()].concat()))
+        .collect();
+
+    write_database(&path, &db).unwrap();
+
+    let len = std::fs::metadata(&path).unwrap().len();
+    assert_eq!(len, (HEADER_SIZE + 100 * 8) as u64);
+
+    let read = read_database(&path).unwrap();
+    assert_eq!(read.len(), 100);
+    assert!(read.iter().zip(db.iter()).all(|(a, b)| a.as_slice() == b.as_slice()));
+
+    let read = read_contiguous_database(&path).unwrap();
+    assert_eq!(read.len(), 100);
+    assert_eq!(read.element_size(), 8);
+    assert!(db.iter().enumerate().all(|(i, x)| read.record(i) == x.as_slice()));
+}
+
+#[test]
+fn test_raw() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.bin");
+
+    std::fs::write(&path, (0..=255u8).collect::<Vec<u8>>()).unwrap();
+
+    let db = read_raw_database(&path, 16).unwrap();
+    assert_eq!(db.len(), 16);
+    assert_eq!(db[1].as_slice(), &(16..32u8).collect::<Vec<u8>>()[..]);
+
+    assert!(matches!(read_raw_database(&path, 17), Err(RaidPirError::InvalidDatabase(_))));
+}
+
+#[test]
+fn test_invalid() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.raidpir");
+
+    let db = vec![RaidPirData::new(vec![1; 8]), RaidPirData::new(vec![2; 4])];
+    assert!(matches!(write_database(&path, &db), Err(RaidPirError::ElementSize { expected: 8, actual: 4 })));
+
+    std::fs::write(&path, b"definitely not a database").unwrap();
+    assert!(matches!(read_database(&path), Err(RaidPirError::Io(_))));
+
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(&[0; HEADER_SIZE]).unwrap();
+    assert!(matches!(read_database(&path), Err(RaidPirError::InvalidDatabase(_))));
+
+    // truncated records
+    let header = DatabaseHeader { element_size: 8, blocks: 10 };
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(&header.to_bytes()).unwrap();
+    file.write_all(&[0; 8 * 9]).unwrap();
+    assert!(matches!(read_database(&path), Err(RaidPirError::InvalidDatabase(_))));
+
+    // sizes that wrap around to just the header
+    let header = DatabaseHeader { element_size: usize::MAX / 2 + 1, blocks: 2 };
+    std::fs::write(&path, header.to_bytes()).unwrap();
+    assert!(matches!(read_database(&path), Err(RaidPirError::InvalidDatabase(_))));
+    assert!(matches!(read_contiguous_database(&path), Err(RaidPirError::InvalidDatabase(_))));
+    assert!(matches!(MappedDatabase::open(&path), Err(RaidPirError::InvalidDatabase(_))));
+}
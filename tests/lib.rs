@@ -1,7 +1,11 @@
+use std::sync::Arc;
+
 use rand::rngs::StdRng; // TODO: different PRNGs?
 use rand::{RngCore, SeedableRng};
 
 use raidpir::client::RaidPirClient;
+use raidpir::error::RaidPirError;
+use raidpir::params::RaidPirParams;
 use raidpir::server::RaidPirServer;
 use raidpir::types::RaidPirData;
 
@@ -14,24 +18,27 @@ fn test_raidpir_redundancies() {
         db.push(prng.next_u32());
     }
 
+    let db = Arc::new(db);
     for redundancy in 2..=4 {
+        let params = RaidPirParams::new(db.len(), 4, redundancy, 4).unwrap();
+
         let mut servers: Vec<RaidPirServer<u32>> = (0..4)
-            .map(|i| RaidPirServer::new(db.clone(), i, 4, redundancy, true))
+            .map(|i| RaidPirServer::with_shared(db.clone(), i, params, true).unwrap())
             .collect();
 
-        let client = RaidPirClient::new(db.len(), 4, redundancy);
+        let client = RaidPirClient::new(params);
 
-        let seeds = servers.iter_mut().map(|s| s.seed()).collect();
+        let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
 
-        let queries = client.query(42, &seeds);
+        let queries = client.query(42, &seeds).unwrap();
 
         let responses: Vec<u32> = servers
             .iter_mut()
             .zip(seeds.iter().zip(queries.iter()))
-            .map(|(server, (seed, query))| server.response(seed.clone(), query))
+            .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
             .collect();
 
-        assert!(client.combine(responses) == db[42]);
+        assert!(client.combine(responses).unwrap() == db[42]);
     }
 }
 
@@ -44,23 +51,76 @@ fn test_large_db() {
         db.push(prng.next_u32());
     }
 
+    let db = Arc::new(db);
+    let params = RaidPirParams::new(db.len(), 8, 5, 4).unwrap();
+
     let mut servers: Vec<RaidPirServer<u32>> = (0..8)
-        .map(|i| RaidPirServer::new(db.clone(), i, 8, 5, false))
+        .map(|i| RaidPirServer::with_shared(db.clone(), i, params, false).unwrap())
         .collect();
 
-    let client = RaidPirClient::new(db.len(), 8, 5);
+    // All servers reference the same database.
+    assert_eq!(Arc::strong_count(&db), 9);
+
+    let client = RaidPirClient::new(params);
 
-    let seeds = servers.iter_mut().map(|s| s.seed()).collect();
+    let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
 
-    let queries = client.query(1 << 4, &seeds);
+    let queries = client.query(1 << 4, &seeds).unwrap();
 
     let responses: Vec<u32> = servers
         .iter_mut()
         .zip(seeds.iter().zip(queries.iter()))
-        .map(|(server, (seed, query))| server.response(seed.clone(), query))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
         .collect();
 
-    assert!(client.combine(responses) == db[1 << 4]);
+    assert!(client.combine(responses).unwrap() == db[1 << 4]);
+}
+
+#[test]
+fn test_parallel_preprocess() {
+    let mut prng = StdRng::from_entropy();
+
+    let mut db: Vec<u64> = Vec::with_capacity(1000);
+    for _i in 0..1000 {
+        db.push(prng.next_u64());
+    }
+
+    let params = RaidPirParams::new(db.len(), 3, 3, 8).unwrap();
+
+    let servers: Vec<RaidPirServer<u64>> = (0..3)
+        .map(|i| {
+            let mut server = RaidPirServer::new(db.clone(), i, params, true).unwrap();
+            server.set_parallelism(i + 1).unwrap();
+            server.preprocess();
+            server
+        })
+        .collect();
+
+    let client = RaidPirClient::new(params);
+
+    // More lookups than fit into the queue, so it is refilled in between.
+    for index in (0..db.len()).step_by(10) {
+        let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+
+        let queries = client.query(index, &seeds).unwrap();
+
+        let responses: Vec<u64> = servers
+            .iter()
+            .zip(seeds.iter().zip(queries.iter()))
+            .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+            .collect();
+
+        assert_eq!(client.combine(responses).unwrap(), db[index]);
+    }
+
+    // Concurrent refills don't overfill the queue.
+    let server = RaidPirServer::new(db.clone(), 0, params, false).unwrap();
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| server.preprocess());
+        }
+    });
+    assert_eq!(server.queued(), raidpir::server::DEFAULT_QUEUE_CAPACITY);
 }
 
 #[test]
@@ -72,23 +132,60 @@ fn test_padding() {
         db.push(prng.next_u32());
     }
 
+    let db = Arc::new(db);
+    let params = RaidPirParams::new(db.len(), 4, 2, 4).unwrap();
+
     let mut servers: Vec<RaidPirServer<u32>> = (0..4)
-        .map(|i| RaidPirServer::new(db.clone(), i, 4, 2, true))
+        .map(|i| RaidPirServer::with_shared(db.clone(), i, params, true).unwrap())
         .collect();
 
-    let client = RaidPirClient::new(db.len(), 4, 2);
+    let client = RaidPirClient::new(params);
 
-    let seeds = servers.iter_mut().map(|s| s.seed()).collect();
+    let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
 
-    let queries = client.query(123, &seeds);
+    let queries = client.query(123, &seeds).unwrap();
 
     let responses: Vec<u32> = servers
         .iter_mut()
         .zip(seeds.iter().zip(queries.iter()))
-        .map(|(server, (seed, query))| server.response(seed.clone(), query))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
         .collect();
 
-    assert!(client.combine(responses) == db[123]);
+    assert!(client.combine(responses).unwrap() == db[123]);
+}
+
+#[test]
+fn test_padding_bytes() {
+    let mut prng = StdRng::from_entropy();
+
+    let db: Vec<RaidPirData> = (0..100)
+        .map(|_| RaidPirData::new((0..8).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+    let db = Arc::new(db);
+
+    // Most of the windows of the last servers are padding, so their answers
+    // often don't include any record.
+    let params = RaidPirParams::new(db.len(), 8, 2, 8).unwrap();
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..8)
+        .map(|i| RaidPirServer::with_shared(db.clone(), i, params, i % 2 == 1).unwrap())
+        .collect();
+
+    let client = RaidPirClient::new(params);
+
+    for _ in 0..200 {
+        let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+        let queries = client.query(42, &seeds).unwrap();
+
+        let responses: Vec<RaidPirData> = servers
+            .iter()
+            .zip(seeds.iter().zip(queries.iter()))
+            .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+            .collect();
+        assert!(responses.iter().all(|x| x.data.len() == 8));
+
+        assert_eq!(client.combine(responses).unwrap().data, db[42].data);
+    }
 }
 
 #[test]
@@ -103,22 +200,720 @@ fn test_bytes() {
     }
     db[42] = RaidPirData::new(b"deadbeef".to_vec());
 
+    let db = Arc::new(db);
+    let params = RaidPirParams::new(db.len(), 4, 2, 8).unwrap();
+
     let mut servers: Vec<RaidPirServer<RaidPirData>> = (0..4)
-        .map(|i| RaidPirServer::new(db.clone(), i, 4, 2, true))
+        .map(|i| RaidPirServer::with_shared(db.clone(), i, params, true).unwrap())
         .collect();
 
-    let client = RaidPirClient::new(db.len(), 4, 2);
+    let client = RaidPirClient::new(params);
 
-    let seeds = servers.iter_mut().map(|s| s.seed()).collect();
+    let seeds: Vec<u128> = servers.iter_mut().map(|s| s.seed().unwrap()).collect();
 
-    let queries = client.query(42, &seeds);
+    let queries = client.query(42, &seeds).unwrap();
 
     let responses: Vec<RaidPirData> = servers
         .iter_mut()
         .zip(seeds.iter().zip(queries.iter()))
-        .map(|(server, (seed, query))| server.response(seed.clone(), query))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
         .collect();
 
-    let response = client.combine(responses);
+    let response = client.combine(responses).unwrap();
     assert!(response.as_slice() == b"deadbeef");
 }
+
+#[test]
+fn test_errors() {
+    let db: Vec<u32> = (0..256).collect();
+
+    assert!(matches!(RaidPirParams::new(db.len(), 4, 5, 4), Err(RaidPirError::InvalidParams(_))));
+    assert!(matches!(RaidPirParams::new(db.len(), 4, 1, 4), Err(RaidPirError::InvalidParams(_))));
+
+    let params = RaidPirParams::new(db.len(), 4, 2, 4).unwrap();
+
+    assert!(matches!(RaidPirServer::new(db.clone(), 4, params, true), Err(RaidPirError::InvalidParams(_))));
+    assert!(matches!(RaidPirServer::new(db[..100].to_vec(), 0, params, true), Err(RaidPirError::InvalidParams(_))));
+
+    let bytes = vec![RaidPirData::new(vec![0; 8]), RaidPirData::new(vec![0; 4])];
+    assert!(matches!(
+        RaidPirServer::new(bytes, 0, RaidPirParams::new(2, 2, 2, 8).unwrap(), true),
+        Err(RaidPirError::ElementSize { expected: 8, actual: 4 })
+    ));
+
+    let server = RaidPirServer::new(db.clone(), 0, params, true).unwrap();
+    let client = RaidPirClient::new(params);
+
+    assert!(matches!(client.query(256, &[0; 4]), Err(RaidPirError::IndexOutOfRange { .. })));
+    assert!(matches!(client.query(0, &[0; 3]), Err(RaidPirError::ServerCount { expected: 4, actual: 3 })));
+
+    let seed = server.seed().unwrap();
+    let queries = client.query(42, &[seed; 4]).unwrap();
+
+    let mut short = queries[0].clone();
+    short.truncate(8);
+    assert!(matches!(server.response(seed, &short), Err(RaidPirError::QueryLength { expected: 64, actual: 8 })));
+
+    assert!(server.response(seed, &queries[0]).is_ok());
+    assert!(matches!(server.response(seed, &queries[0]), Err(RaidPirError::UnknownSeed(s)) if s == seed));
+}
+
+#[test]
+fn test_params_fingerprint() {
+    let params = RaidPirParams::new(420, 4, 2, 4).unwrap();
+
+    let server = RaidPirServer::new(vec![0u32; 420], 1, params, false).unwrap();
+    let client = RaidPirClient::new(params);
+
+    assert_eq!(server.params().fingerprint(), client.params().fingerprint());
+    assert!(client.params().check_fingerprint(server.params().fingerprint()).is_ok());
+
+    let other = RaidPirParams::new(420, 4, 3, 4).unwrap();
+    assert!(matches!(
+        client.params().check_fingerprint(other.fingerprint()),
+        Err(RaidPirError::ParamsMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_worker() {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    let wait_for = |server: &RaidPirServer<u32>, queued: usize| {
+        let start = Instant::now();
+        while server.queued() != queued {
+            assert!(start.elapsed() < Duration::from_secs(10), "queue stuck at {}", server.queued());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    let params = RaidPirParams::new(256, 2, 2, 4).unwrap();
+
+    let mut server = RaidPirServer::new(vec![0u32; 256], 0, params, false).unwrap();
+    assert!(server.set_queue_size(0, 0).is_err());
+    assert!(server.set_queue_size(4, 5).is_err());
+    server.set_queue_size(16, 8).unwrap();
+
+    let server = Arc::new(server);
+    let worker = server.start_worker().unwrap();
+    assert!(server.start_worker().is_err());
+
+    wait_for(&server, 16);
+
+    // Dropping to the low-water mark isn't enough to trigger a refill.
+    for _i in 0..8 {
+        server.seed().unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(server.queued(), 8);
+
+    server.seed().unwrap();
+    wait_for(&server, 16);
+
+    worker.stop();
+    assert_eq!(Arc::strong_count(&server), 1);
+
+    for _i in 0..10 {
+        server.seed().unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(server.queued(), 6);
+
+    // Can be restarted after stopping.
+    let _worker = server.start_worker().unwrap();
+    wait_for(&server, 16);
+}
+
+#[test]
+fn test_seed_expiry() {
+    use std::time::Duration;
+
+    let params = RaidPirParams::new(256, 2, 2, 4).unwrap();
+    let client = RaidPirClient::new(params);
+
+    let mut server = RaidPirServer::new(vec![7u32; 256], 0, params, false).unwrap();
+    server.set_seed_expiry(None, Some(4)).unwrap();
+
+    let seeds: Vec<u128> = (0..6).map(|_| server.seed().unwrap()).collect();
+    assert_eq!(server.handed_out().unwrap(), 4);
+    assert_eq!(server.evicted(), 2);
+
+    // The two oldest seeds are gone, the others still work.
+    let query = client.query(0, &[seeds[0], seeds[0]]).unwrap().remove(0);
+    assert!(matches!(server.response(seeds[0], &query), Err(RaidPirError::UnknownSeed(_))));
+    let query = client.query(0, &[seeds[5], seeds[5]]).unwrap().remove(0);
+    assert!(server.response(seeds[5], &query).is_ok());
+    assert_eq!(server.handed_out().unwrap(), 3);
+
+    let mut server = RaidPirServer::new(vec![7u32; 256], 0, params, false).unwrap();
+    server.set_seed_expiry(Some(Duration::from_millis(50)), None).unwrap();
+
+    let seed = server.seed().unwrap();
+    std::thread::sleep(Duration::from_millis(60));
+    server.evict_expired().unwrap();
+
+    assert_eq!(server.handed_out().unwrap(), 0);
+    assert_eq!(server.evicted(), 1);
+    let query = client.query(0, &[seed, seed]).unwrap().remove(0);
+    assert!(matches!(server.response(seed, &query), Err(RaidPirError::UnknownSeed(_))));
+}
+
+#[test]
+fn test_stateless_seeds() {
+    use std::time::Duration;
+
+    let ttl = Some(Duration::from_secs(600));
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<u32> = (0..256).map(|_| prng.next_u32()).collect();
+
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+    let client = RaidPirClient::new(params);
+
+    // Two instances of each server, sharing a key per server id. Without a
+    // shared seed store, each instance only knows the seeds it answered.
+    let instances: Vec<Vec<RaidPirServer<u32>>> = (0..2)
+        .map(|i| {
+            (0..2)
+                .map(|_| {
+                    let mut server = RaidPirServer::new(db.clone(), i, params, true).unwrap();
+                    server.set_seed_expiry(ttl, None).unwrap();
+                    server.set_seed_key([i as u8; 16]).unwrap();
+                    server
+                })
+                .collect()
+        })
+        .collect();
+
+    // Seeds from the first instances are answered on demand by the second.
+    let seeds: Vec<u128> = instances.iter().map(|s| s[0].seed().unwrap()).collect();
+    assert!(instances.iter().zip(seeds.iter()).all(|(s, seed)| s[1].verify_seed(*seed)));
+
+    let queries = client.query(42, &seeds).unwrap();
+    let responses: Vec<u32> = instances
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(s, (seed, query))| s[1].response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap(), db[42]);
+
+    // Answered seeds can't be replayed, even though they still verify.
+    assert!(instances[0][1].verify_seed(seeds[0]));
+    assert!(matches!(instances[0][1].response(seeds[0], &queries[0]), Err(RaidPirError::UnknownSeed(_))));
+
+    // Seeds are bound to the key, and forgeries are rejected.
+    let query = client.query(0, &seeds).unwrap().remove(0);
+    assert!(!instances[1][0].verify_seed(seeds[0]));
+    assert!(matches!(instances[1][0].response(seeds[0], &query), Err(RaidPirError::UnknownSeed(_))));
+    assert!(matches!(instances[0][1].response(seeds[0] ^ 1, &query), Err(RaidPirError::UnknownSeed(_))));
+
+    // Evicted seeds are still answered.
+    let mut server = RaidPirServer::new(db.clone(), 0, params, false).unwrap();
+    server.set_seed_expiry(ttl, Some(1)).unwrap();
+    server.set_seed_key([0; 16]).unwrap();
+
+    let seeds = [server.seed().unwrap(), instances[1][0].seed().unwrap()];
+    server.seed().unwrap();
+    assert_eq!(server.evicted(), 1);
+
+    let queries = client.query(7, &seeds).unwrap();
+    let responses = vec![
+        server.response(seeds[0], &queries[0]).unwrap(),
+        instances[1][0].response(seeds[1], &queries[1]).unwrap(),
+    ];
+    assert_eq!(client.combine(responses).unwrap(), db[7]);
+
+    // Neither seeds answered on demand nor stored ones are answered twice.
+    assert!(matches!(server.response(seeds[0], &queries[0]), Err(RaidPirError::UnknownSeed(_))));
+    assert!(matches!(instances[1][0].response(seeds[1], &queries[1]), Err(RaidPirError::UnknownSeed(_))));
+
+    // Instances sharing a seed store as well answer each seed only once.
+    let dir = tempfile::tempdir().unwrap();
+    let replicas: Vec<RaidPirServer<u32>> = (0..2)
+        .map(|_| {
+            let mut server = RaidPirServer::new(db.clone(), 0, params, false).unwrap();
+            server.set_seed_expiry(ttl, None).unwrap();
+            server.set_seed_key([0; 16]).unwrap();
+            server.set_seed_store(raidpir::store::FileStore::open(dir.path(), 4).unwrap());
+            server
+        })
+        .collect();
+
+    let seeds = [replicas[0].seed().unwrap(), instances[1][0].seed().unwrap()];
+    let query = client.query(0, &seeds).unwrap().remove(0);
+    replicas[1].response(seeds[0], &query).unwrap();
+    assert!(matches!(replicas[0].response(seeds[0], &query), Err(RaidPirError::UnknownSeed(_))));
+    assert!(matches!(replicas[1].response(seeds[0], &query), Err(RaidPirError::UnknownSeed(_))));
+
+    // Stateless seeds need a TTL to bound the redeemed seeds.
+    let mut server = RaidPirServer::new(db.clone(), 0, params, false).unwrap();
+    assert!(matches!(server.set_seed_key([0; 16]), Err(RaidPirError::InvalidParams(_))));
+    server.set_seed_expiry(ttl, None).unwrap();
+    server.set_seed_key([0; 16]).unwrap();
+    assert!(matches!(server.set_seed_expiry(None, None), Err(RaidPirError::InvalidParams(_))));
+}
+
+#[test]
+fn test_shared_seed_store() {
+    use raidpir::store::{FileStore, SeedStore};
+
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<RaidPirData> = (0..64)
+        .map(|_| RaidPirData::new((0..8).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let client = RaidPirClient::new(params);
+    let dir = tempfile::tempdir().unwrap();
+
+    // Two replicas of each server, sharing a store per server id.
+    let instances: Vec<Vec<RaidPirServer<RaidPirData>>> = (0..2)
+        .map(|i| {
+            (0..2)
+                .map(|_| {
+                    let mut server = RaidPirServer::new(db.clone(), i, params, true).unwrap();
+                    server.set_seed_store(FileStore::open(dir.path().join(i.to_string()), 8).unwrap());
+                    server
+                })
+                .collect()
+        })
+        .collect();
+
+    // Seeds handed out by the first replicas are answered by the second.
+    let seeds: Vec<u128> = instances.iter().map(|s| s[0].seed().unwrap()).collect();
+    assert_eq!(instances[0][1].handed_out().unwrap(), 1);
+
+    let queries = client.query(42, &seeds).unwrap();
+    let responses: Vec<RaidPirData> = instances
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(s, (seed, query))| s[1].response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap().data, db[42].data);
+
+    // Each seed is still only answered once.
+    assert_eq!(instances[0][0].handed_out().unwrap(), 0);
+    assert!(matches!(instances[0][0].response(seeds[0], &queries[0]), Err(RaidPirError::UnknownSeed(_))));
+
+    // Integer elements can be stored as well, but truncated answers are rejected.
+    let store = FileStore::open(dir.path().join("u32"), 4).unwrap();
+    store.insert(1, 0xdeadbeef_u32).unwrap();
+    assert_eq!(store.remove(1).unwrap(), Some(0xdeadbeef_u32));
+    store.insert(2, 7_u32).unwrap();
+    std::fs::write(store.dir().join(format!("{:032x}", 2)), [7, 0]).unwrap();
+    assert!(matches!(
+        SeedStore::<u32>::remove(&store, 2),
+        Err(RaidPirError::ElementSize { expected: 4, actual: 2 })
+    ));
+}
+
+#[test]
+fn test_snapshot() {
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<RaidPirData> = (0..64)
+        .map(|_| RaidPirData::new((0..8).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let client = RaidPirClient::new(params);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("seeds.snapshot");
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..2)
+        .map(|i| RaidPirServer::new(db.clone(), i, params, true).unwrap())
+        .collect();
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    servers[0].save_snapshot(&path).unwrap();
+
+    // A restarted server answers seeds handed out before the restart.
+    let restarted = RaidPirServer::new(db.clone(), 0, params, true).unwrap();
+    assert_eq!(restarted.load_snapshot(&path).unwrap(), servers[0].queued() + 1);
+    assert_eq!(restarted.handed_out().unwrap(), 1);
+
+    let queries = client.query(13, &seeds).unwrap();
+    let responses = vec![
+        restarted.response(seeds[0], &queries[0]).unwrap(),
+        servers[1].response(seeds[1], &queries[1]).unwrap(),
+    ];
+    assert_eq!(client.combine(responses).unwrap().data, db[13].data);
+
+    // Snapshots from other data or server ids are rejected.
+    let mut changed = db.clone();
+    changed[5] = RaidPirData::new(vec![0; 8]);
+    let server = RaidPirServer::new(changed, 0, params, true).unwrap();
+    assert!(matches!(server.load_snapshot(&path), Err(RaidPirError::InvalidSnapshot(_))));
+    assert!(matches!(servers[1].load_snapshot(&path), Err(RaidPirError::InvalidSnapshot(_))));
+    assert_eq!(server.queued(), 0);
+
+    // Entry counts and sizes that overflow are rejected, too.
+    let snapshot = std::fs::read(&path).unwrap();
+    for (offset, value) in [(24, u64::MAX - 8), (32, u64::MAX), (40, u64::MAX), (32, u64::MAX / 24)] {
+        let mut crafted = snapshot.clone();
+        crafted[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        std::fs::write(&path, &crafted).unwrap();
+        assert!(matches!(servers[0].load_snapshot(&path), Err(RaidPirError::InvalidSnapshot(_))));
+    }
+}
+
+#[test]
+fn test_preprocess_russians() {
+    use raidpir::russians::memory_estimate;
+
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<u32> = (0..1000).map(|_| prng.next_u32()).collect();
+
+    let params = RaidPirParams::new(db.len(), 4, 3, 4).unwrap();
+    let client = RaidPirClient::new(params);
+    assert_eq!(memory_estimate(&params, 8, true).unwrap(), 3 * memory_estimate(&params, 8, false).unwrap());
+    assert!(matches!(memory_estimate(&params, 17, false), Err(RaidPirError::InvalidParams(_))));
+    let huge = RaidPirParams::new(usize::MAX / 2, 2, 2, 1 << 20).unwrap();
+    assert!(matches!(memory_estimate(&huge, 8, false), Err(RaidPirError::InvalidParams(_))));
+
+    for (russians, window) in [(false, 8), (true, 8), (true, 4), (true, 12), (true, 16)].iter() {
+        let servers: Vec<RaidPirServer<u32>> = (0..4)
+            .map(|i| {
+                let mut server = RaidPirServer::new(db.clone(), i, params, *russians).unwrap();
+                server.set_russians_window(*window).unwrap();
+                server.set_preprocess_russians(true).unwrap();
+                server
+            })
+            .collect();
+
+        let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+        let queries = client.query(999, &seeds).unwrap();
+        let responses: Vec<u32> = servers
+            .iter()
+            .zip(seeds.iter().zip(queries.iter()))
+            .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+            .collect();
+
+        assert_eq!(client.combine(responses).unwrap(), db[999]);
+    }
+}
+
+#[test]
+fn test_russians_tables() {
+    use raidpir::russians::RussiansTables;
+    use raidpir::util::rand_bitvec;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<u64> = (0..104).map(|_| prng.next_u64()).collect();
+    let bits = rand_bitvec(prng.next_u64() as u128, db.len());
+
+    let expected = bits.iter().zip(db.iter()).filter(|(q, _)| **q).fold(0, |a, (_, x)| a ^ x);
+
+    for window in [1, 3, 8, 12].iter() {
+        let reports = AtomicUsize::new(0);
+        let tables = RussiansTables::with_progress(&db, *window, |done, total| {
+            assert!(done <= total);
+            assert_eq!(total, db.len().div_ceil(*window));
+            reports.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        assert_eq!(reports.load(Ordering::Relaxed), db.len().div_ceil(*window));
+
+        let mut answer = 0;
+        tables.xor_into(&mut answer, bits.as_raw_slice());
+        assert_eq!(answer, expected);
+    }
+}
+
+#[test]
+fn test_mapped_russians() {
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<RaidPirData> = (0..200)
+        .map(|_| RaidPirData::new((0..8).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let client = RaidPirClient::new(params);
+    let dir = tempfile::tempdir().unwrap();
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..2)
+        .map(|i| {
+            let path = dir.path().join(format!("{}.russians", i));
+
+            let mut server = RaidPirServer::new(db.clone(), i, params, true).unwrap();
+            server.set_russians_window(4).unwrap();
+            server.set_preprocess_russians(true).unwrap();
+            server.save_russians(&path).unwrap();
+
+            let mut server = RaidPirServer::new(db.clone(), i, params, false).unwrap();
+            server.load_russians(&path).unwrap();
+            server
+        })
+        .collect();
+
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    let queries = client.query(123, &seeds).unwrap();
+    let responses: Vec<RaidPirData> = servers
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap().data, db[123].data);
+
+    // Tables of another server are rejected.
+    let mut server = RaidPirServer::new(db.clone(), 1, params, false).unwrap();
+    let path = dir.path().join("0.russians");
+    assert!(matches!(server.load_russians(&path), Err(RaidPirError::InvalidDatabase(_))));
+
+    // Table lengths whose size overflows are rejected.
+    let path = dir.path().join("1.russians");
+    let mut file = std::fs::read(&path).unwrap();
+    file[40..48].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&path, &file).unwrap();
+    assert!(matches!(server.load_russians(&path), Err(RaidPirError::InvalidDatabase(_))));
+}
+
+#[test]
+fn test_contiguous_database() {
+    use raidpir::storage::RaidPirDatabase;
+
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<u32> = (0..300).map(|_| prng.next_u32()).collect();
+
+    let mut contiguous = RaidPirDatabase::with_capacity(4, db.len()).unwrap();
+    db.iter().for_each(|x| contiguous.push(&x.to_le_bytes()).unwrap());
+    assert!(matches!(contiguous.push(&[0; 8]), Err(RaidPirError::ElementSize { .. })));
+
+    let params = RaidPirParams::new(db.len(), 4, 3, 4).unwrap();
+    let client = RaidPirClient::new(params);
+
+    // Records can be served as integers, or as byte arrays.
+    let servers: Vec<RaidPirServer<u32>> = (0..4)
+        .map(|i| RaidPirServer::with_storage(contiguous.clone(), i, params, i % 2 == 0).unwrap())
+        .collect();
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    let queries = client.query(299, &seeds).unwrap();
+    let responses: Vec<u32> = servers
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap(), db[299]);
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..4)
+        .map(|i| RaidPirServer::with_storage(contiguous.clone(), i, params, false).unwrap())
+        .collect();
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    let queries = client.query(5, &seeds).unwrap();
+    let responses: Vec<RaidPirData> = servers
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap().data, db[5].to_le_bytes());
+
+    // Integers of the wrong size can't be served.
+    assert!(matches!(
+        RaidPirServer::<u64>::with_storage(contiguous, 0, params, false),
+        Err(RaidPirError::ElementSize { .. })
+    ));
+}
+
+#[test]
+fn test_mapped_database() {
+    use raidpir::database::write_database;
+    use raidpir::storage::MappedDatabase;
+
+    let mut prng = StdRng::from_entropy();
+    let db: Vec<RaidPirData> = (0..100)
+        .map(|_| RaidPirData::new((0..16).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.raidpir");
+    write_database(&path, &db).unwrap();
+
+    let raw = dir.path().join("db.bin");
+    std::fs::write(&raw, db.iter().flat_map(|x| x.data.clone()).collect::<Vec<u8>>()).unwrap();
+
+    let params = RaidPirParams::new(db.len(), 3, 2, 16).unwrap();
+    let client = RaidPirClient::new(params);
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..3)
+        .map(|i| {
+            let mapped = match i {
+                0 => MappedDatabase::open_raw(&raw, 16).unwrap(),
+                _ => MappedDatabase::open(&path).unwrap(),
+            };
+            RaidPirServer::with_storage(mapped, i, params, i == 1).unwrap()
+        })
+        .collect();
+
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    let queries = client.query(77, &seeds).unwrap();
+    let responses: Vec<RaidPirData> = servers
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap().data, db[77].data);
+
+    assert!(matches!(MappedDatabase::open(&raw), Err(RaidPirError::InvalidDatabase(_))));
+    assert!(matches!(MappedDatabase::open_raw(&raw, 17), Err(RaidPirError::InvalidDatabase(_))));
+}
+
+#[test]
+fn test_window() {
+    use raidpir::storage::RaidPirDatabase;
+
+    let mut prng = StdRng::from_entropy();
+
+    // Sizes where the windows wrap around, end in the padding, or consist
+    // only of padding.
+    for &(blocks, servers, redundancy) in &[(100, 4, 2), (300, 4, 3), (1, 4, 2), (20, 8, 3), (64, 4, 4)] {
+        let db: Vec<u32> = (0..blocks).map(|_| prng.next_u32()).collect();
+        let params = RaidPirParams::new(db.len(), servers, redundancy, 4).unwrap();
+        let client = RaidPirClient::new(params);
+
+        let (full, windowed): (Vec<RaidPirServer<u32>>, Vec<RaidPirServer<u32>>) = (0..servers)
+            .map(|i| {
+                let window: Vec<u32> = params.window(i).into_iter().flat_map(|x| db[x].to_vec()).collect();
+                assert!(window.len() <= params.window_len());
+
+                let full = RaidPirServer::new(db.clone(), i, params, false).unwrap();
+                let windowed = RaidPirServer::with_window(window, i, params, i % 2 == 0).unwrap();
+                assert_eq!(full.digest(), windowed.digest());
+
+                (full, windowed)
+            })
+            .unzip();
+
+        for servers in &[full, windowed] {
+            for (index, x) in db.iter().enumerate() {
+                let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+                let queries = client.query(index, &seeds).unwrap();
+
+                let responses: Vec<u32> = servers
+                    .iter()
+                    .zip(seeds.iter().zip(queries.iter()))
+                    .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+                    .collect();
+
+                assert_eq!(client.combine(responses).unwrap(), *x);
+            }
+        }
+    }
+
+    let db: Vec<RaidPirData> = (0..50)
+        .map(|_| RaidPirData::new((0..8).map(|_| prng.next_u32() as u8).collect()))
+        .collect();
+    let params = RaidPirParams::new(db.len(), 3, 2, 8).unwrap();
+    let client = RaidPirClient::new(params);
+
+    let servers: Vec<RaidPirServer<RaidPirData>> = (0..3)
+        .map(|i| {
+            let window = RaidPirDatabase::from_window(&db, i, &params).unwrap();
+            RaidPirServer::with_window(window, i, params, true).unwrap()
+        })
+        .collect();
+
+    let seeds: Vec<u128> = servers.iter().map(|s| s.seed().unwrap()).collect();
+    let queries = client.query(33, &seeds).unwrap();
+    let responses: Vec<RaidPirData> = servers
+        .iter()
+        .zip(seeds.iter().zip(queries.iter()))
+        .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+        .collect();
+    assert_eq!(client.combine(responses).unwrap().data, db[33].data);
+
+    assert!(matches!(
+        RaidPirServer::with_window(db.clone(), 0, params, false),
+        Err(RaidPirError::InvalidParams(_))
+    ));
+}
+
+#[test]
+fn test_update() {
+    use raidpir::storage::{MappedDatabase, RaidPirDatabase};
+    use raidpir::store::FileStore;
+
+    let mut prng = StdRng::from_entropy();
+    let mut db: Vec<u32> = (0..300).map(|_| prng.next_u32()).collect();
+    let params = RaidPirParams::new(db.len(), 4, 3, 4).unwrap();
+    let client = RaidPirClient::new(params);
+
+    // With and without tables, storing the whole database or the window.
+    let servers: Vec<RaidPirServer<u32>> = (0..4)
+        .map(|i| {
+            let mut server = match i {
+                0 => RaidPirServer::new(db.clone(), i, params, true).unwrap(),
+                1 => RaidPirServer::new(db.clone(), i, params, false).unwrap(),
+                2 => {
+                    let contiguous = RaidPirDatabase::from_window(&db, i, &params).unwrap();
+                    RaidPirServer::with_window(contiguous, i, params, true).unwrap()
+                }
+                _ => {
+                    let contiguous = RaidPirDatabase::from_window(&db, i, &params).unwrap();
+                    RaidPirServer::with_window(contiguous, i, params, false).unwrap()
+                }
+            };
+            server.set_preprocess_russians(i % 2 == 0).unwrap();
+            server.preprocess();
+            server
+        })
+        .collect();
+
+    // Seeds handed out before the update, and seeds still in the queue.
+    let handed_out: Vec<Vec<u128>> = (0..10)
+        .map(|_| servers.iter().map(|s| s.seed().unwrap()).collect())
+        .collect();
+
+    let mut updates: Vec<(usize, u32)> = (0..40).map(|_| (prng.next_u32() as usize % 300, prng.next_u32())).collect();
+    updates.push((updates[0].0, prng.next_u32()));
+    for (index, value) in updates.iter() {
+        db[*index] = *value;
+    }
+
+    for (i, server) in servers.iter().enumerate() {
+        server.update(updates[1].0, updates[1].1).unwrap();
+        server.update_batch(updates.clone()).unwrap();
+
+        let rebuilt: RaidPirServer<u32> = RaidPirServer::new(db.clone(), i, params, false).unwrap();
+        assert_eq!(server.digest(), rebuilt.digest());
+    }
+
+    let fresh: Vec<Vec<u128>> = (0..10)
+        .map(|_| servers.iter().map(|s| s.seed().unwrap()).collect())
+        .collect();
+
+    for (seeds, (index, _)) in handed_out.iter().chain(fresh.iter()).zip(updates.iter().cycle()) {
+        let queries = client.query(*index, seeds).unwrap();
+        let responses: Vec<u32> = servers
+            .iter()
+            .zip(seeds.iter().zip(queries.iter()))
+            .map(|(server, (seed, query))| server.response(*seed, query).unwrap())
+            .collect();
+        assert_eq!(client.combine(responses).unwrap(), db[*index]);
+    }
+
+    assert!(matches!(servers[0].update(300, 0), Err(RaidPirError::IndexOutOfRange { .. })));
+
+    // Shared or memory-mapped databases can't be updated.
+    let shared = Arc::new(db.clone());
+    let server = RaidPirServer::with_shared(shared.clone(), 0, params, false).unwrap();
+    assert!(matches!(server.update(0, 0), Err(RaidPirError::ReadOnly(_))));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.bin");
+    std::fs::write(&path, db.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+    let server: RaidPirServer<u32> =
+        RaidPirServer::with_storage(MappedDatabase::open_raw(&path, 4).unwrap(), 0, params, false).unwrap();
+    let digest = server.digest();
+    assert!(matches!(server.update_batch(vec![(0, 0), (1, 1)]), Err(RaidPirError::ReadOnly(_))));
+    assert_eq!(server.digest(), digest);
+
+    // Shared seed stores can't be patched, so granted seeds stay answerable.
+    let mut server = RaidPirServer::new(db.clone(), 0, params, false).unwrap();
+    server.set_seed_store(FileStore::open(dir.path().join("seeds"), 4).unwrap());
+    let seed = server.seed().unwrap();
+    let digest = server.digest();
+    assert!(matches!(server.update_batch((75..225).map(|i| (i, !db[i]))), Err(RaidPirError::ReadOnly(_))));
+    assert_eq!(server.digest(), digest);
+    assert_eq!(server.handed_out().unwrap(), 1);
+    let query = raidpir::util::rand_bitvec(seed, params.blocks_per_server());
+    assert!(server.response(seed, &query).is_ok());
+}
@@ -0,0 +1,91 @@
+use std::io::Cursor;
+
+use bitvec::prelude::*;
+
+use raidpir::error::RaidPirError;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::*;
+
+#[test]
+fn test_roundtrip() {
+    let params = RaidPirParams::new(420, 4, 2, 1024).unwrap();
+
+    let mut query: BitVec<Lsb0, u8> = BitVec::from_vec(vec![0xde, 0xad, 0xbe, 0xef]);
+    query.truncate(27);
+
+    let messages = [
+        Message::Hello(params),
+        Message::SeedGrant(u128::MAX - 42),
+        Message::Query { seed: 1234, query },
+        Message::Response(b"deadbeef".to_vec()),
+        Message::Response(vec![0xab; 1 << 20]),
+        Message::Error("unknown seed".to_string()),
+        Message::SeedRequest(16),
+    ];
+
+    let mut stream = Vec::new();
+    for msg in messages.iter() {
+        assert_eq!(&decode(&encode(msg)).unwrap(), msg);
+        write_message(&mut stream, msg).unwrap();
+    }
+
+    let mut stream = Cursor::new(stream);
+    for msg in messages.iter() {
+        assert_eq!(&read_message(&mut stream).unwrap(), msg);
+    }
+
+    assert!(matches!(read_message(&mut stream), Err(RaidPirError::Io(_))));
+}
+
+#[test]
+fn test_malformed() {
+    let frame = encode(&Message::SeedGrant(1234));
+
+    // truncated
+    assert!(matches!(decode(&frame[..frame.len() - 1]), Err(RaidPirError::Protocol(_))));
+    assert!(matches!(decode(&frame[..2]), Err(RaidPirError::Protocol(_))));
+
+    // wrong version
+    let mut bad = frame.clone();
+    bad[4] = PROTOCOL_VERSION + 1;
+    assert!(matches!(decode(&bad), Err(RaidPirError::Protocol(_))));
+
+    // unknown type
+    let mut bad = frame.clone();
+    bad[5] = 0xff;
+    assert!(matches!(decode(&bad), Err(RaidPirError::Protocol(_))));
+
+    // trailing bytes
+    let mut bad = frame.clone();
+    bad.push(0);
+    bad[0] += 1;
+    assert!(matches!(decode(&bad), Err(RaidPirError::Protocol(_))));
+
+    // query with inconsistent bit length
+    let mut bad = encode(&Message::Query { seed: 0, query: BitVec::from_vec(vec![0; 8]) });
+    bad[22] = 128;
+    assert!(matches!(decode(&bad), Err(RaidPirError::Protocol(_))));
+
+    // oversized length field
+    let mut stream = Cursor::new(vec![0xff; 8]);
+    assert!(matches!(read_message(&mut stream), Err(RaidPirError::Protocol(_))));
+
+    // large length field, but the frame ends early
+    let mut short = ((MAX_FRAME_SIZE - 1) as u32).to_le_bytes().to_vec();
+    short.extend_from_slice(&frame[4..]);
+    let mut stream = Cursor::new(short);
+    assert!(matches!(read_message(&mut stream), Err(RaidPirError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+
+    // parameters whose padding overflows
+    let mut bad = encode(&Message::Hello(RaidPirParams::new(420, 4, 2, 1024).unwrap()));
+    bad[16..24].copy_from_slice(&(1u64 << 61).to_le_bytes());
+    assert!(matches!(decode(&bad), Err(RaidPirError::InvalidParams(_))));
+    bad[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+    bad[16..24].copy_from_slice(&3u64.to_le_bytes());
+    assert!(matches!(decode(&bad), Err(RaidPirError::InvalidParams(_))));
+
+    // tampered parameters
+    let mut bad = encode(&Message::Hello(RaidPirParams::new(420, 4, 2, 1024).unwrap()));
+    bad[8] ^= 1;
+    assert!(matches!(decode(&bad), Err(RaidPirError::ParamsMismatch { .. })));
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "cli")]
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use raidpir::client::RaidPirClient;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::Message;
+use raidpir::transport::{TcpTransport, Transport};
+
+/// Kills the server when the test ends, even if it fails.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn connect(address: &str) -> TcpTransport {
+    let start = Instant::now();
+    loop {
+        match TcpTransport::connect(address, Duration::from_secs(10)) {
+            Ok(transport) => return transport,
+            Err(_) if start.elapsed() < Duration::from_secs(30) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("server did not come up: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_idle_connections() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.bin");
+    let db: Vec<u8> = (0..64u32).flat_map(|x| x.to_le_bytes()).collect();
+    std::fs::write(&path, &db).unwrap();
+
+    let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let _server = Server(
+        Command::new(env!("CARGO_BIN_EXE_raidpir-server"))
+            .args(["--database", path.to_str().unwrap(), "--raw-element-size", "4"])
+            .args(["--id", "0", "--servers", "2", "--listen", &address, "--no-worker"])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    // More idle clients than the machine has CPUs, and they never query.
+    let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let idle: Vec<TcpTransport> = (0..cpus + 1)
+        .map(|_| {
+            let mut transport = connect(&address);
+            assert!(matches!(transport.recv().unwrap(), Message::Hello(_)));
+            assert!(matches!(transport.recv().unwrap(), Message::SeedGrant(_)));
+            transport
+        })
+        .collect();
+
+    let mut transport = connect(&address);
+    transport.set_timeout(Some(Duration::from_secs(10))).unwrap();
+
+    let params = match transport.recv().unwrap() {
+        Message::Hello(params) => params,
+        msg => panic!("unexpected {:?}", msg),
+    };
+    assert_eq!(params, RaidPirParams::new(64, 2, 2, 4).unwrap());
+    let seed = match transport.recv().unwrap() {
+        Message::SeedGrant(seed) => seed,
+        msg => panic!("unexpected {:?}", msg),
+    };
+
+    let query = RaidPirClient::new(params).query(0, &[seed, seed]).unwrap().remove(0);
+    transport.send(&Message::Query { seed, query }).unwrap();
+    assert!(matches!(transport.recv().unwrap(), Message::Response(r) if r.len() == 4));
+
+    drop(idle);
+}
@@ -0,0 +1,208 @@
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use raidpir::client::RaidPirClient;
+use raidpir::error::RaidPirError;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::Message;
+use raidpir::server::RaidPirServer;
+use raidpir::service::*;
+use raidpir::transport::*;
+
+mod common;
+use common::{random_db, servers};
+
+#[test]
+fn test_tcp() {
+    let db = random_db(300, 16);
+    let params = RaidPirParams::new(db.len(), 3, 2, 16).unwrap();
+
+    let addresses: Vec<_> = servers(&db, params)
+        .into_iter()
+        .map(|server| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(&server, &mut TcpTransport::new(stream)).unwrap();
+            });
+
+            address
+        })
+        .collect();
+
+    let mut transports: Vec<TcpTransport> = addresses
+        .iter()
+        .map(|a| TcpTransport::connect(a, Duration::from_secs(10)).unwrap())
+        .collect();
+
+    let response = lookup(&mut transports, 123).unwrap();
+    assert_eq!(response.as_slice(), db[123].as_slice());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix() {
+    use std::os::unix::net::UnixListener;
+
+    let db = random_db(300, 16);
+    let params = RaidPirParams::new(db.len(), 2, 2, 16).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    let paths: Vec<_> = servers(&db, params)
+        .into_iter()
+        .enumerate()
+        .map(|(i, server)| {
+            let path = dir.path().join(format!("server{}.sock", i));
+            let listener = UnixListener::bind(&path).unwrap();
+
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(&server, &mut UnixTransport::new(stream)).unwrap();
+            });
+
+            path
+        })
+        .collect();
+
+    let mut transports: Vec<UnixTransport> = paths
+        .iter()
+        .map(|p| UnixTransport::connect(p, Duration::from_secs(10)).unwrap())
+        .collect();
+
+    let response = lookup(&mut transports, 42).unwrap();
+    assert_eq!(response.as_slice(), db[42].as_slice());
+}
+
+#[test]
+fn test_channel() {
+    let db = random_db(300, 16);
+    let params = RaidPirParams::new(db.len(), 4, 3, 16).unwrap();
+
+    let mut transports: Vec<ChannelTransport> = servers(&db, params)
+        .into_iter()
+        .map(|server| {
+            let (client, mut transport) = ChannelTransport::pair();
+            thread::spawn(move || serve_connection(&server, &mut transport).unwrap());
+            client
+        })
+        .collect();
+
+    let response = lookup(&mut transports, 299).unwrap();
+    assert_eq!(response.as_slice(), db[299].as_slice());
+}
+
+#[test]
+fn test_integer_elements() {
+    let db: Vec<u32> = (0..100).map(|x| x * 0x01010101).collect();
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+
+    let mut transports: Vec<ChannelTransport> = (0..2)
+        .map(|i| {
+            let server = RaidPirServer::new(db.clone(), i, params, true).unwrap();
+            let (client, mut transport) = ChannelTransport::pair();
+            thread::spawn(move || serve_connection(&server, &mut transport).unwrap());
+            client
+        })
+        .collect();
+
+    let response = lookup(&mut transports, 42).unwrap();
+    assert_eq!(response.as_slice(), db[42].to_le_bytes());
+}
+
+#[test]
+fn test_wrong_seed() {
+    let db = random_db(64, 4);
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+    let server = RaidPirServer::new(db, 0, params, false).unwrap();
+
+    let (mut client, mut transport) = ChannelTransport::pair();
+    let handle = thread::spawn(move || serve_connection(&server, &mut transport));
+
+    client.recv().unwrap();
+    let seed = match client.recv().unwrap() {
+        Message::SeedGrant(seed) => seed,
+        msg => panic!("unexpected {:?}", msg),
+    };
+
+    let query = RaidPirClient::new(params).query(0, &[seed, seed]).unwrap().remove(0);
+    client.send(&Message::Query { seed: seed ^ 1, query }).unwrap();
+
+    assert!(matches!(client.recv().unwrap(), Message::Error(_)));
+    assert!(matches!(handle.join().unwrap(), Err(RaidPirError::UnknownSeed(_))));
+}
+
+#[test]
+fn test_persistent() {
+    let db = random_db(300, 16);
+    let params = RaidPirParams::new(db.len(), 2, 2, 16).unwrap();
+
+    let (addresses, handles): (Vec<_>, Vec<_>) = servers(&db, params)
+        .into_iter()
+        .map(|server| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let handle = thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(&server, &mut TcpTransport::new(stream))
+            });
+
+            (address, handle)
+        })
+        .unzip();
+
+    let mut connections: Vec<Connection<TcpTransport>> = addresses
+        .iter()
+        .map(|a| Connection::open(TcpTransport::connect(a, Duration::from_secs(10)).unwrap()).unwrap())
+        .collect();
+
+    for indices in [vec![5], vec![1, 2, 3, 299, 0], (0..40).collect()].iter() {
+        let responses = lookup_many(&mut connections, indices, 4).unwrap();
+        for (index, response) in indices.iter().zip(responses.iter()) {
+            assert_eq!(response.as_slice(), db[*index].as_slice());
+        }
+    }
+
+    // Closing the connection ends the session cleanly.
+    drop(connections);
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn test_seed_limit() {
+    let db = random_db(64, 4);
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+    let server = RaidPirServer::new(db, 0, params, false).unwrap();
+
+    let (client, mut transport) = ChannelTransport::pair();
+    let handle = thread::spawn(move || serve_connection(&server, &mut transport));
+
+    let mut connection = Connection::open(client).unwrap();
+    connection.reserve_seeds(MAX_OUTSTANDING_SEEDS).unwrap();
+    assert_eq!(connection.available_seeds(), MAX_OUTSTANDING_SEEDS);
+
+    assert!(matches!(connection.reserve_seeds(MAX_OUTSTANDING_SEEDS + 1), Err(RaidPirError::Remote(_))));
+    assert!(matches!(handle.join().unwrap(), Err(RaidPirError::Protocol(_))));
+}
+
+#[test]
+fn test_seed_limit_evicted() {
+    let db = random_db(64, 4);
+    let params = RaidPirParams::new(db.len(), 2, 2, 4).unwrap();
+    let mut server = RaidPirServer::new(db, 0, params, false).unwrap();
+    server.set_seed_expiry(None, Some(16)).unwrap();
+
+    let (client, mut transport) = ChannelTransport::pair();
+    thread::spawn(move || serve_connection(&server, &mut transport));
+
+    // Seeds evicted by the server don't count as outstanding.
+    let mut connection = Connection::open(client).unwrap();
+    connection.reserve_seeds(MAX_OUTSTANDING_SEEDS).unwrap();
+    connection.reserve_seeds(2 * MAX_OUTSTANDING_SEEDS - 16).unwrap();
+    assert_eq!(connection.available_seeds(), 2 * MAX_OUTSTANDING_SEEDS - 16);
+}
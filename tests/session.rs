@@ -0,0 +1,174 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use raidpir::error::RaidPirError;
+use raidpir::params::RaidPirParams;
+use raidpir::protocol::Message;
+use raidpir::pool::SeedPool;
+use raidpir::server::RaidPirServer;
+use raidpir::service::{lookup_prepared, serve_connection, Connection, DEFAULT_PIPELINE_DEPTH};
+use raidpir::session::RaidPirSession;
+use raidpir::transport::{ChannelTransport, Transport};
+use raidpir::types::RaidPirData;
+
+mod common;
+use common::{random_db, servers};
+
+fn spawn(server: &Arc<RaidPirServer<RaidPirData>>) -> ChannelTransport {
+    let (client, mut transport) = ChannelTransport::pair();
+    let server = server.clone();
+    thread::spawn(move || serve_connection(&server, &mut transport));
+    client
+}
+
+#[test]
+fn test_lookup() {
+    let db = random_db(300, 16);
+    let params = RaidPirParams::new(db.len(), 3, 2, 16).unwrap();
+    let servers = servers(&db, params);
+
+    let mut session = RaidPirSession::new(3, |i, _| Ok(spawn(&servers[i])));
+
+    for index in [0, 123, 299].iter() {
+        assert_eq!(session.lookup(*index).unwrap().as_slice(), db[*index].as_slice());
+    }
+    assert_eq!(session.params(), Some(&params));
+
+    assert!(matches!(session.lookup(300), Err(RaidPirError::IndexOutOfRange { .. })));
+}
+
+#[test]
+fn test_persistent() {
+    let db = random_db(500, 8);
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let servers = servers(&db, params);
+
+    let connects = AtomicUsize::new(0);
+    let mut session = RaidPirSession::new(2, |i, _| {
+        connects.fetch_add(1, Ordering::SeqCst);
+        Ok(spawn(&servers[i]))
+    });
+
+    // More indices than seeds are requested at once.
+    let indices: Vec<usize> = (0..db.len()).rev().collect();
+    let responses = session.lookup_many(&indices).unwrap();
+    for (index, response) in indices.iter().zip(responses.iter()) {
+        assert_eq!(response.as_slice(), db[*index].as_slice());
+    }
+
+    for (index, element) in db.iter().enumerate().take(10) {
+        assert_eq!(session.lookup(index).unwrap().as_slice(), element.as_slice());
+    }
+
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_prefetch() {
+    let db = random_db(200, 8);
+    let params = RaidPirParams::new(db.len(), 3, 3, 8).unwrap();
+    let servers = servers(&db, params);
+
+    let mut session = RaidPirSession::new(3, |i, _| Ok(spawn(&servers[i])));
+    session.prefetch(5).unwrap();
+
+    // Uses up the prefetched seeds, and then some.
+    let indices = [199, 0, 17, 17, 42, 100, 3];
+    let responses = session.lookup_many(&indices).unwrap();
+    for (index, response) in indices.iter().zip(responses.iter()) {
+        assert_eq!(response.as_slice(), db[*index].as_slice());
+    }
+}
+
+#[test]
+fn test_pool() {
+    let db = random_db(100, 8);
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let servers = servers(&db, params);
+
+    let mut connections: Vec<Connection<ChannelTransport>> =
+        servers.iter().map(|server| Connection::open(spawn(server)).unwrap()).collect();
+
+    let mut pool = SeedPool::new(Duration::from_millis(200));
+    pool.fill(&mut connections, 4).unwrap();
+    pool.fill(&mut connections, 3).unwrap();
+    assert_eq!(pool.len(), 4);
+
+    let prepared = pool.take(3);
+    assert_eq!(pool.len(), 1);
+
+    let responses = lookup_prepared(&mut connections, &[1, 2, 3], prepared, DEFAULT_PIPELINE_DEPTH).unwrap();
+    for (index, response) in [1, 2, 3].iter().zip(responses.iter()) {
+        assert_eq!(response.as_slice(), db[*index].as_slice());
+    }
+
+    thread::sleep(Duration::from_millis(250));
+    assert!(pool.is_empty());
+    assert!(pool.take(1).is_empty());
+}
+
+#[test]
+fn test_retry() {
+    let db = random_db(100, 8);
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let servers = servers(&db, params);
+
+    // Server 1 reports an error on its first two connections.
+    let failures = AtomicUsize::new(0);
+    let mut session = RaidPirSession::new(2, |i, _| {
+        if i == 1 && failures.fetch_add(1, Ordering::SeqCst) < 2 {
+            let (client, mut transport) = ChannelTransport::pair();
+            transport.send(&Message::Error("overloaded".to_string()))?;
+            return Ok(client);
+        }
+        Ok(spawn(&servers[i]))
+    });
+
+    assert_eq!(session.lookup(42).unwrap().as_slice(), db[42].as_slice());
+    assert_eq!(failures.load(Ordering::SeqCst), 3);
+
+    // Without retries, the misbehaving server is reported.
+    failures.store(0, Ordering::SeqCst);
+    session.set_retries(0);
+    session.disconnect();
+
+    match session.lookup(42) {
+        Err(RaidPirError::Server { server: 1, error }) => assert!(matches!(*error, RaidPirError::Remote(_))),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn test_timeout() {
+    let db = random_db(100, 8);
+    let params = RaidPirParams::new(db.len(), 2, 2, 8).unwrap();
+    let servers = servers(&db, params);
+
+    // Server 0 accepts connections, but never says anything.
+    let silent = Mutex::new(Vec::new());
+    let mut session = RaidPirSession::new(2, |i, _| {
+        if i == 0 {
+            let (client, transport) = ChannelTransport::pair();
+            silent.lock().unwrap().push(transport);
+            return Ok(client);
+        }
+        Ok(spawn(&servers[i]))
+    });
+    session.set_timeout(Duration::from_millis(100));
+    session.set_retries(1);
+
+    let start = Instant::now();
+
+    match session.lookup(7) {
+        Err(RaidPirError::Server { server: 0, error }) => match *error {
+            RaidPirError::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+            e => panic!("unexpected {:?}", e),
+        },
+        result => panic!("unexpected {:?}", result),
+    }
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(silent.lock().unwrap().len(), 2);
+}